@@ -0,0 +1,194 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC};
+use sha2::{Digest, Sha224, Sha256, Sha384, Sha512};
+
+/// Characters to be escaped by url-encode. Unreserved characters in RFC 3986 are kept.
+const URL_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Transform applied to the holes in-process instead of the targeted command (--builtin)
+#[derive(Debug, PartialEq)]
+pub enum Builtin {
+    Upper,
+    Lower,
+    Trim,
+    Mask(char),      // mask[:<char>]
+    Redact(usize),   // redact[:<number of visible characters>]
+    Base64Encode,
+    Base64Decode,
+    HexEncode,
+    HexDecode,
+    UrlEncode,
+    UrlDecode,
+    Hash(String),    // hash[:sha224|sha256|sha384|sha512]
+    Counter(Option<i64>, i64), // counter[:<start>[:<step>]], holds the next value (None if it overflows) and the step
+    Constant(String),  // constant:<string>
+}
+
+impl Builtin {
+    /// Parse <name>[:<args>]
+    ///   "mask:#" => Mask('#')
+    ///   "counter:10:5" => Counter(Some(10), 5)
+    pub fn parse(spec: &str) -> Result<Builtin, String> {
+        let (name, arg) = match spec.find(':') {
+            Some(i) => (&spec[..i], Some(&spec[i + 1..])),
+            None => (spec, None),
+        };
+        let invalid = || format!("Invalid argument of --builtin {}: '{}'", name, arg.unwrap_or(""));
+        let b = match (name, arg) {
+            ("upper", None) => Builtin::Upper,
+            ("lower", None) => Builtin::Lower,
+            ("trim", None) => Builtin::Trim,
+            ("mask", None) => Builtin::Mask('*'),
+            ("mask", Some(a)) => {
+                let mut chars = a.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => Builtin::Mask(c),
+                    _ => return Err(invalid()),
+                }
+            }
+            ("redact", None) => Builtin::Redact(4),
+            ("redact", Some(a)) => Builtin::Redact(a.parse().map_err(|_| invalid())?),
+            ("base64-encode", None) => Builtin::Base64Encode,
+            ("base64-decode", None) => Builtin::Base64Decode,
+            ("hex-encode", None) => Builtin::HexEncode,
+            ("hex-decode", None) => Builtin::HexDecode,
+            ("url-encode", None) => Builtin::UrlEncode,
+            ("url-decode", None) => Builtin::UrlDecode,
+            ("hash", None) => Builtin::Hash("sha256".to_string()),
+            ("hash", Some(a)) => match a {
+                "sha224" | "sha256" | "sha384" | "sha512" => Builtin::Hash(a.to_string()),
+                _ => return Err(invalid()),
+            },
+            ("counter", None) => Builtin::Counter(Some(1), 1),
+            ("counter", Some(a)) => {
+                let mut nums = a.splitn(2, ':').map(|n| n.parse::<i64>());
+                let start = nums.next().unwrap().map_err(|_| invalid())?;
+                let step = nums.next().unwrap_or(Ok(1)).map_err(|_| invalid())?;
+                Builtin::Counter(Some(start), step)
+            }
+            ("constant", Some(a)) => Builtin::Constant(a.to_string()),
+            ("upper", _) | ("lower", _) | ("trim", _) | ("base64-encode", _) | ("base64-decode", _)
+            | ("hex-encode", _) | ("hex-decode", _) | ("url-encode", _) | ("url-decode", _) => {
+                return Err(format!("--builtin {} does not take any argument", name))
+            }
+            ("constant", None) => return Err("--builtin constant requires :<string>".to_string()),
+            _ => return Err(format!("Unknown --builtin: '{}'", name)),
+        };
+        Ok(b)
+    }
+
+    /// Transform the string in the hole.
+    pub fn apply(&mut self, input: &str) -> Result<String, String> {
+        let result = match self {
+            Builtin::Upper => input.to_uppercase(),
+            Builtin::Lower => input.to_lowercase(),
+            Builtin::Trim => input.trim().to_string(),
+            Builtin::Mask(c) => input.chars().map(|_| *c).collect(),
+            Builtin::Redact(visible) => {
+                let len = input.chars().count();
+                let hidden = len.saturating_sub(*visible);
+                input
+                    .chars()
+                    .enumerate()
+                    .map(|(i, c)| if i < hidden { '*' } else { c })
+                    .collect()
+            }
+            Builtin::Base64Encode => STANDARD.encode(input),
+            Builtin::Base64Decode => {
+                let bytes = STANDARD
+                    .decode(input)
+                    .map_err(|e| format!("base64-decode: {}", e))?;
+                String::from_utf8_lossy(&bytes).to_string()
+            }
+            Builtin::HexEncode => hex::encode(input),
+            Builtin::HexDecode => {
+                let bytes = hex::decode(input).map_err(|e| format!("hex-decode: {}", e))?;
+                String::from_utf8_lossy(&bytes).to_string()
+            }
+            Builtin::UrlEncode => percent_encoding::utf8_percent_encode(input, URL_ENCODE_SET).to_string(),
+            Builtin::UrlDecode => percent_encoding::percent_decode_str(input)
+                .decode_utf8_lossy()
+                .to_string(),
+            Builtin::Hash(algo) => match algo.as_str() {
+                "sha224" => hex::encode(Sha224::digest(input.as_bytes())),
+                "sha384" => hex::encode(Sha384::digest(input.as_bytes())),
+                "sha512" => hex::encode(Sha512::digest(input.as_bytes())),
+                _ => hex::encode(Sha256::digest(input.as_bytes())),
+            },
+            Builtin::Counter(next, step) => {
+                let n = next.ok_or("counter: The value exceeds the range of 64-bit integer")?;
+                *next = n.checked_add(*step);
+                n.to_string()
+            }
+            Builtin::Constant(s) => s.to_string(),
+        };
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn apply(spec: &str, input: &str) -> Result<String, String> {
+        Builtin::parse(spec)?.apply(input)
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Builtin::parse("mask:#"), Ok(Builtin::Mask('#')));
+        assert_eq!(Builtin::parse("counter:10:5"), Ok(Builtin::Counter(Some(10), 5)));
+        assert_eq!(Builtin::parse("constant:a:b"), Ok(Builtin::Constant("a:b".to_string())));
+        assert!(Builtin::parse("mask:ab").is_err());
+        assert!(Builtin::parse("hash:md5").is_err());
+        assert!(Builtin::parse("upper:1").is_err());
+        assert!(Builtin::parse("constant").is_err());
+        assert!(Builtin::parse("nothing").is_err());
+    }
+
+    #[test]
+    fn test_apply() {
+        assert_eq!(apply("upper", "abc"), Ok("ABC".to_string()));
+        assert_eq!(apply("lower", "ABC"), Ok("abc".to_string()));
+        assert_eq!(apply("trim", "  a b  "), Ok("a b".to_string()));
+        assert_eq!(apply("mask", "あいう"), Ok("***".to_string()));
+        assert_eq!(apply("redact", "1234567890"), Ok("******7890".to_string()));
+        assert_eq!(apply("redact:0", "123"), Ok("***".to_string()));
+        assert_eq!(apply("base64-encode", "hello"), Ok("aGVsbG8=".to_string()));
+        assert_eq!(apply("base64-decode", "aGVsbG8="), Ok("hello".to_string()));
+        assert!(apply("base64-decode", "!!").is_err());
+        assert_eq!(apply("hex-encode", "AB"), Ok("4142".to_string()));
+        assert_eq!(apply("hex-decode", "4142"), Ok("AB".to_string()));
+        assert_eq!(apply("url-encode", "a b/c~"), Ok("a%20b%2Fc~".to_string()));
+        assert_eq!(apply("url-decode", "a%20b%2Fc"), Ok("a b/c".to_string()));
+        assert_eq!(
+            apply("hash", "abc"),
+            Ok("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad".to_string())
+        );
+        assert_eq!(apply("constant:x", "abc"), Ok("x".to_string()));
+    }
+
+    #[test]
+    fn test_counter() {
+        let mut b = Builtin::parse("counter:10:-2").unwrap();
+        assert_eq!(b.apply("a"), Ok("10".to_string()));
+        assert_eq!(b.apply("b"), Ok("8".to_string()));
+        assert_eq!(b.apply("c"), Ok("6".to_string()));
+    }
+
+    #[test]
+    fn test_counter_overflow() {
+        let mut b = Builtin::parse("counter:9223372036854775807").unwrap();
+        assert_eq!(b.apply("a"), Ok("9223372036854775807".to_string()));
+        assert!(b.apply("b").is_err());
+        let mut b = Builtin::parse("counter:-9223372036854775807:-1").unwrap();
+        assert_eq!(b.apply("a"), Ok("-9223372036854775807".to_string()));
+        assert_eq!(b.apply("b"), Ok("-9223372036854775808".to_string()));
+        assert!(b.apply("c").is_err());
+    }
+}
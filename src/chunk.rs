@@ -1,7 +1,115 @@
-/// Input stream is devided into multiple Chunks
-pub enum Chunk {
-    Keep(String),   // a string under masking tape. Printed as is.
-    Hole,           // A hole on the masking tape. The string in the hole being processed other thread.
-    SHole(String),  // Solid hole. A hole and string in this hole. Enabled with -s (solid mode)
-    EOF,            // End of file
+use std::error;
+use std::fmt;
+use std::io;
+
+/// Error while sending the chunks to the `Emitter`
+pub enum ChunkSendError {
+    Channel,          // The receiver of the chunks has gone
+    Pipe(io::Error),  // Failed to write the chunks
+    Input(io::Error), // Failed to read the input, or to start the pipeline of -e
+}
+
+impl fmt::Display for ChunkSendError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ChunkSendError::Channel => write!(f, "Channel error: sending on a closed channel"),
+            ChunkSendError::Pipe(ref err) => write!(f, "IO error: {}", err),
+            ChunkSendError::Input(ref err) => write!(f, "IO error: {}", err),
+        }
+    }
+}
+
+impl error::Error for ChunkSendError {
+    fn description(&self) -> &str {
+        match *self {
+            ChunkSendError::Channel => "Channel error",
+            ChunkSendError::Pipe(_) => "IO error",
+            ChunkSendError::Input(_) => "IO error",
+        }
+    }
+}
+
+impl fmt::Debug for ChunkSendError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// Which part of the line a hole is made from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Index {
+    None,
+    Field(usize), // N-th field of the line (-f, --csv)
+    Match(usize), // N-th match in the line (-o -g)
+}
+
+/// Where a hole is located in the input
+#[derive(Clone, Debug, PartialEq)]
+pub struct Location {
+    pub seq: u64,         // Sequence number of the hole, starting from 1
+    pub line: u64,        // Line number where the hole starts, starting from 1
+    pub byte_offset: u64, // Offset in bytes from the beginning of the input
+    pub index: Index,
+}
+
+/// Receiver of the chunks which the input is divided into.
+/// The input is restored by concatenating the strings in the order they are sent.
+pub trait Emitter {
+    /// A string under the masking tape. It must be printed as is.
+    fn send_keep(&mut self, msg: String) -> Result<(), ChunkSendError>;
+
+    /// A string in the hole for the `target`-th transform (`-f 2 -- cmdA ::: -f 5 -- cmdB`), and where it is.
+    fn send_byps_to(&mut self, target: usize, msg: String, loc: Location) -> Result<(), ChunkSendError>;
+
+    /// End of the input.
+    fn send_eof(&mut self) -> Result<(), ChunkSendError>;
+
+    /// The input is idle. The chunks so far should be printed without waiting for more.
+    fn send_flush(&mut self) -> Result<(), ChunkSendError> {
+        Ok(())
+    }
+}
+
+/// Count lines and bytes of the chunks to tell the location of the holes
+pub struct Tracker {
+    line_end: u8,
+    seq: u64,    // number of holes so far
+    line: u64,   // current line number
+    offset: u64, // number of bytes so far
+}
+
+impl Tracker {
+    pub fn new(line_end: u8) -> Tracker {
+        Tracker {
+            line_end,
+            seq: 0,
+            line: 1,
+            offset: 0,
+        }
+    }
+
+    /// Count the lines and bytes from the beginning of another input. The sequence number continues.
+    pub fn start_over(&mut self) {
+        self.line = 1;
+        self.offset = 0;
+    }
+
+    /// Count a string under the masking tape
+    pub fn keep(&mut self, msg: &str) {
+        self.offset += msg.len() as u64;
+        self.line += msg.bytes().filter(|b| *b == self.line_end).count() as u64;
+    }
+
+    /// Count a string in the hole and return where it is
+    pub fn hole(&mut self, msg: &str, index: Index) -> Location {
+        self.seq += 1;
+        let loc = Location {
+            seq: self.seq,
+            line: self.line,
+            byte_offset: self.offset,
+            index,
+        };
+        self.keep(msg);
+        loc
+    }
 }
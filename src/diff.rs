@@ -0,0 +1,262 @@
+use super::report::Report;
+use teip::Location;
+use std::collections::VecDeque;
+use std::io::{self, Write};
+
+/// Number of the unchanged lines around the changes
+const CONTEXT: usize = 3;
+
+/// Unified diff between the input and the output of teip (--diff)
+///
+/// teip knows which parts of the input are changed. A line end under the masking tape is the end of
+/// a line in both the input and the output, so the lines between such line ends are compared as a block.
+/// It does not look for the shortest diff but needs neither the entire input nor the time of a diff algorithm.
+pub struct Differ {
+    old_label: String,
+    new_label: String,
+    line_end: u8,
+    old: Vec<u8>, // Lines of the input since the last common line end
+    new: Vec<u8>, // Lines of the output since the last common line end
+    old_nr: u64,  // Number of the lines of the input so far
+    new_nr: u64,
+    context: VecDeque<Vec<u8>>, // Unchanged lines which are not printed yet
+    hunk: Option<Hunk>,
+    header: bool, // Whether the file names are printed
+}
+
+struct Hunk {
+    old_start: u64,
+    new_start: u64,
+    old_len: u64,
+    new_len: u64,
+    lines: Vec<u8>,
+}
+
+impl Differ {
+    /// "-" is the standard input. Other paths are labeled "a/<path>" and "b/<path>" like git.
+    pub fn new(path: &str, line_end: u8) -> Differ {
+        let (old_label, new_label) = match path {
+            "-" => ("-".to_string(), "-".to_string()),
+            _ => (format!("a/{}", path), format!("b/{}", path)),
+        };
+        Differ {
+            old_label,
+            new_label,
+            line_end,
+            old: Vec::new(),
+            new: Vec::new(),
+            old_nr: 0,
+            new_nr: 0,
+            context: VecDeque::new(),
+            hunk: None,
+            header: false,
+        }
+    }
+
+    /// Compare the lines since the last common line end
+    fn compare(&mut self, out: &mut dyn Write) -> io::Result<()> {
+        let old = std::mem::take(&mut self.old);
+        let new = std::mem::take(&mut self.new);
+        let line_end = self.line_end;
+        if old == new {
+            for line in old.split_inclusive(|b| *b == line_end) {
+                self.unchanged(line.to_vec(), out)?;
+            }
+            return Ok(());
+        }
+        let before = self.context.len() as u64;
+        let (old_start, new_start) = (self.old_nr + 1 - before, self.new_nr + 1 - before);
+        let hunk = self.hunk.get_or_insert_with(|| Hunk {
+            old_start,
+            new_start,
+            old_len: 0,
+            new_len: 0,
+            lines: Vec::new(),
+        });
+        for line in self.context.drain(..) {
+            push_line(&mut hunk.lines, b' ', &line, line_end);
+            hunk.old_len += 1;
+            hunk.new_len += 1;
+        }
+        for line in old.split_inclusive(|b| *b == line_end) {
+            push_line(&mut hunk.lines, b'-', line, line_end);
+            hunk.old_len += 1;
+            self.old_nr += 1;
+        }
+        for line in new.split_inclusive(|b| *b == line_end) {
+            push_line(&mut hunk.lines, b'+', line, line_end);
+            hunk.new_len += 1;
+            self.new_nr += 1;
+        }
+        Ok(())
+    }
+
+    fn unchanged(&mut self, line: Vec<u8>, out: &mut dyn Write) -> io::Result<()> {
+        self.old_nr += 1;
+        self.new_nr += 1;
+        self.context.push_back(line);
+        if self.hunk.is_none() {
+            if self.context.len() > CONTEXT {
+                self.context.pop_front();
+            }
+        } else if self.context.len() > CONTEXT * 2 {
+            // The next change is too far to be in the same hunk
+            self.close(out)?;
+        }
+        Ok(())
+    }
+
+    /// Print the hunk with the unchanged lines after it, leaving the lines before the next hunk
+    fn close(&mut self, out: &mut dyn Write) -> io::Result<()> {
+        let mut hunk = match self.hunk.take() {
+            Some(hunk) => hunk,
+            None => return Ok(()),
+        };
+        let line_end = self.line_end;
+        let trailing = self.context.len().min(CONTEXT);
+        for line in self.context.drain(..trailing) {
+            push_line(&mut hunk.lines, b' ', &line, line_end);
+            hunk.old_len += 1;
+            hunk.new_len += 1;
+        }
+        while self.context.len() > CONTEXT {
+            self.context.pop_front();
+        }
+        if !self.header {
+            writeln!(out, "--- {}", self.old_label)?;
+            writeln!(out, "+++ {}", self.new_label)?;
+            self.header = true;
+        }
+        writeln!(
+            out,
+            "@@ -{} +{} @@",
+            range(hunk.old_start, hunk.old_len),
+            range(hunk.new_start, hunk.new_len)
+        )?;
+        out.write_all(&hunk.lines)
+    }
+}
+
+impl Report for Differ {
+    /// The string is in both the input and the output
+    fn keep(&mut self, msg: &str, out: &mut dyn Write) -> io::Result<()> {
+        let line_end = self.line_end;
+        for piece in msg.as_bytes().split_inclusive(|b| *b == line_end) {
+            self.old.extend_from_slice(piece);
+            self.new.extend_from_slice(piece);
+            if piece.last() == Some(&line_end) {
+                self.compare(out)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn hole(&mut self, original: &str, result: &str, _loc: &Location, _out: &mut dyn Write) -> io::Result<()> {
+        self.old.extend_from_slice(original.as_bytes());
+        self.new.extend_from_slice(result.as_bytes());
+        Ok(())
+    }
+
+    fn finish(&mut self, out: &mut dyn Write) -> io::Result<()> {
+        self.compare(out)?;
+        self.close(out)
+    }
+}
+
+/// "<start>,<length>" of the hunk header. An empty range starts at the line before it.
+fn range(start: u64, len: u64) -> String {
+    match len {
+        0 => format!("{},0", start - 1),
+        1 => format!("{}", start),
+        _ => format!("{},{}", start, len),
+    }
+}
+
+fn push_line(lines: &mut Vec<u8>, prefix: u8, line: &[u8], line_end: u8) {
+    lines.push(prefix);
+    lines.extend_from_slice(line);
+    if line.last() != Some(&line_end) {
+        lines.extend_from_slice(b"\n\\ No newline at end of file\n");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use teip::Index;
+
+    fn diff(chunks: &[(&str, Option<&str>)]) -> String {
+        let mut differ = Differ::new("file", b'\n');
+        let mut out = Vec::new();
+        let loc = Location { seq: 1, line: 1, byte_offset: 0, index: Index::None };
+        for (old, new) in chunks {
+            match new {
+                Some(new) => differ.hole(old, new, &loc, &mut out).unwrap(),
+                None => differ.keep(old, &mut out).unwrap(),
+            }
+        }
+        differ.finish(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_unchanged() {
+        assert_eq!(diff(&[("AAA\n", None), ("BBB", Some("BBB")), ("\n", None)]), "");
+    }
+
+    #[test]
+    fn test_context() {
+        let mut chunks = Vec::new();
+        let lines: Vec<String> = (1..=10).map(|i| format!("{}", i)).collect();
+        for (i, line) in lines.iter().enumerate() {
+            if i == 4 {
+                chunks.push((line.as_str(), Some("five")));
+            } else {
+                chunks.push((line.as_str(), None));
+            }
+            chunks.push(("\n", None));
+        }
+        assert_eq!(
+            diff(&chunks),
+            "--- a/file\n+++ b/file\n@@ -2,7 +2,7 @@\n 2\n 3\n 4\n-5\n+five\n 6\n 7\n 8\n"
+        );
+    }
+
+    #[test]
+    fn test_hunks() {
+        // Changes within 6 lines are in the same hunk
+        let mut chunks = Vec::new();
+        let lines: Vec<String> = (1..=20).map(|i| format!("{}", i)).collect();
+        for (i, line) in lines.iter().enumerate() {
+            if i == 0 || i == 7 || i == 19 {
+                chunks.push((line.as_str(), Some("x")));
+            } else {
+                chunks.push((line.as_str(), None));
+            }
+            chunks.push(("\n", None));
+        }
+        assert_eq!(
+            diff(&chunks),
+            "--- a/file\n+++ b/file\n\
+             @@ -1,11 +1,11 @@\n-1\n+x\n 2\n 3\n 4\n 5\n 6\n 7\n-8\n+x\n 9\n 10\n 11\n\
+             @@ -17,4 +17,4 @@\n 17\n 18\n 19\n-20\n+x\n"
+        );
+    }
+
+    #[test]
+    fn test_lines_added() {
+        // A hole can be filled with more lines. The line end in the hole is not a common one.
+        assert_eq!(
+            diff(&[("A\n", None), ("B\nC", Some("b")), ("\n", None)]),
+            "--- a/file\n+++ b/file\n@@ -1,3 +1,2 @@\n A\n-B\n-C\n+b\n"
+        );
+    }
+
+    #[test]
+    fn test_no_newline() {
+        assert_eq!(
+            diff(&[("A\n", None), ("B", Some("b"))]),
+            "--- a/file\n+++ b/file\n@@ -1,2 +1,2 @@\n A\n-B\n\\ No newline at end of file\n+b\n\\ No newline at end of file\n"
+        );
+    }
+}
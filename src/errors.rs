@@ -1,17 +1,42 @@
-use super::chunk::Chunk;
 use super::CMD;
 use std::error;
 use std::fmt;
-use std::sync::mpsc;
+use std::fs;
+use std::path::PathBuf;
+use std::process::ExitStatus;
+use std::sync::Mutex;
 use log::debug;
 
+pub use teip::ChunkSendError;
+
+lazy_static! {
+    static ref PARTIAL_FILE: Mutex<Option<PathBuf>> = Mutex::new(None);
+}
+
+/// Remove the file at `path` if teip exits with an error before it is completed (-i).
+/// None forgets the file given before.
+pub fn remove_on_exit(path: Option<PathBuf>) {
+    if let Ok(mut partial) = PARTIAL_FILE.lock() {
+        *partial = path;
+    }
+}
+
+fn exit(code: i32) -> ! {
+    if let Ok(mut partial) = PARTIAL_FILE.lock() {
+        if let Some(path) = partial.take() {
+            let _ = fs::remove_file(path);
+        }
+    }
+    std::process::exit(code);
+}
+
 pub fn msg_error(msg: &str) {
     eprintln!("{}: {}", CMD, msg);
 }
 
 pub fn error_exit(msg: &str) -> ! {
     msg_error(msg);
-    std::process::exit(1);
+    exit(1);
 }
 
 // If something very sad happens to you, run it
@@ -24,72 +49,108 @@ pub fn u() -> ! {
 /// Exit silently because the error can be intentional.
 pub fn exit_silently(msg: &str) -> ! {
     debug!("SIGPIPE?:{}", msg);
-    std::process::exit(1);
+    exit(1);
 }
 
 
-const PIPE_ERROR_MSG: &'static str = "Output of targeted command has been exhausted";
+/// Exit status of teip when the targeted command exited with non-zero status.
+pub const EXIT_CHILD_FAILED: i32 = 123;
+/// Exit status of teip when the targeted command was terminated by a signal.
+pub const EXIT_CHILD_SIGNALED: i32 = 125;
 
-pub enum PipeReceiveError {
-    EndOfFd,
-    Io(std::io::Error),
+/// Targeted command which did not finish successfully.
+pub struct ChildFailure {
+    pub cmd: String,
+    pub hole: Option<u64>, // Sequence number of the hole (-s only)
+    pub status: ExitStatus,
 }
 
-impl fmt::Display for PipeReceiveError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
-            PipeReceiveError::EndOfFd => write!(f, "{}", PIPE_ERROR_MSG),
-            PipeReceiveError::Io(ref e) => write!(f, "IO error: {}", e),
+impl ChildFailure {
+    /// Return None if the command finished successfully
+    pub fn check(cmd: &str, hole: Option<u64>, status: ExitStatus) -> Option<ChildFailure> {
+        if status.success() {
+            return None;
         }
+        Some(ChildFailure {
+            cmd: cmd.to_string(),
+            hole,
+            status,
+        })
     }
-}
 
-impl error::Error for PipeReceiveError {
-    fn description(&self) -> &str {
-        match *self {
-            PipeReceiveError::EndOfFd => PIPE_ERROR_MSG,
-            PipeReceiveError::Io(_) => "IO error",
+    fn signal(&self) -> Option<i32> {
+        cfg_if::cfg_if! {
+            if #[cfg(unix)] {
+                use std::os::unix::process::ExitStatusExt;
+                self.status.signal()
+            } else {
+                None
+            }
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self.signal() {
+            Some(_) => EXIT_CHILD_SIGNALED,
+            None => EXIT_CHILD_FAILED,
         }
     }
 }
 
-impl fmt::Debug for PipeReceiveError {
+impl fmt::Display for ChildFailure {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
-            PipeReceiveError::EndOfFd => write!(f, "{}", PIPE_ERROR_MSG),
-            PipeReceiveError::Io(ref e) => write!(f, "IO error: {}", e),
+        write!(f, "{}", self.cmd)?;
+        if let Some(n) = self.hole {
+            write!(f, " (hole #{})", n)?;
+        }
+        match (self.status.code(), self.signal()) {
+            (Some(code), _) => write!(f, " exited with status {}", code),
+            (None, Some(sig)) => write!(f, " was terminated by signal {}", sig),
+            (None, None) => write!(f, " was terminated abnormally"),
         }
     }
 }
 
-pub enum ChunkSendError {
-    Channel(mpsc::SendError<Chunk>),
-    Pipe(std::io::Error),
+/// Report failures of the targeted commands and exit.
+/// Termination by signal takes priority over non-zero status.
+pub fn child_failure_exit(failures: &[ChildFailure]) -> ! {
+    for f in failures {
+        msg_error(&f.to_string());
+    }
+    let code = failures.iter().map(|f| f.exit_code()).max().unwrap_or(EXIT_CHILD_FAILED);
+    exit(code);
+}
+
+const PIPE_ERROR_MSG: &'static str = "Output of targeted command has been exhausted";
+
+pub enum PipeReceiveError {
+    EndOfFd,
+    Io(std::io::Error),
 }
 
-impl fmt::Display for ChunkSendError {
+impl fmt::Display for PipeReceiveError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            ChunkSendError::Channel(ref err) => write!(f, "Channel error: {}", err),
-            ChunkSendError::Pipe(ref err) => write!(f, "IO error: {}", err),
+            PipeReceiveError::EndOfFd => write!(f, "{}", PIPE_ERROR_MSG),
+            PipeReceiveError::Io(ref e) => write!(f, "IO error: {}", e),
         }
     }
 }
 
-impl error::Error for ChunkSendError {
+impl error::Error for PipeReceiveError {
     fn description(&self) -> &str {
         match *self {
-            ChunkSendError::Channel(_) => "Channel error",
-            ChunkSendError::Pipe(_) => "IO error",
+            PipeReceiveError::EndOfFd => PIPE_ERROR_MSG,
+            PipeReceiveError::Io(_) => "IO error",
         }
     }
 }
 
-impl fmt::Debug for ChunkSendError {
+impl fmt::Debug for PipeReceiveError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            ChunkSendError::Channel(ref e) => write!(f, "Channel error: {}", e),
-            ChunkSendError::Pipe(ref e) => write!(f, "IO error: {}", e),
+            PipeReceiveError::EndOfFd => write!(f, "{}", PIPE_ERROR_MSG),
+            PipeReceiveError::Io(ref e) => write!(f, "IO error: {}", e),
         }
     }
 }
@@ -132,3 +193,9 @@ impl fmt::Debug for SpawnError {
         }
     }
 }
+
+/// Error of HoleSink while filling a hole
+pub enum SinkError {
+    Hole(String),                         // Reported with the sequence number of the hole
+    Command(String, Option<ChildFailure>), // The targeted command did not give the result. It may have failed.
+}
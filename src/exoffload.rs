@@ -0,0 +1,437 @@
+use super::stringutils::{self, Region};
+use super::DEFAULT_CAP;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, VecDeque};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, Write};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use log::debug;
+
+/// Standard input and output of the pipeline spawned by `exec_pipeline`
+pub type Pipeline = (Box<dyn Write + Send>, BufReader<Box<dyn Read + Send>>);
+
+/// Spawn process with a single string command (pipeline).
+/// Its standard input is `stdin` if given, otherwise a pipe which is returned.
+pub fn exec_pipeline(command: &str, stdin: Option<File>) -> io::Result<Pipeline> {
+    cfg_if::cfg_if! {
+        if #[cfg(windows)] {
+            let (shell, opt) = ("cmd", "/C");
+        } else {
+            let (shell, opt) = ("sh", "-c");
+        }
+    }
+    let mut child = Command::new(shell)
+        .args([opt, command])
+        .stdin(match stdin {
+            Some(f) => Stdio::from(f),
+            None => Stdio::piped(),
+        })
+        .stdout(Stdio::piped())
+        .spawn()?;
+    // The pipe is not made if `stdin` is given
+    let child_stdin: Box<dyn Write + Send> = match child.stdin.take() {
+        Some(s) => Box::new(s),
+        None => Box::new(io::sink()),
+    };
+    let child_stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| io::Error::other("Failed to get FD of stdout for given command"))?;
+    Ok((child_stdin, BufReader::new(Box::new(child_stdout))))
+}
+
+/// Maximum size of the lines kept in the `Window` which the pipeline has already resolved
+const WINDOW_SIZE: usize = 1024 * 1024;
+
+/// Lines of the input which the main thread has not taken yet (-e)
+///
+/// The main thread cannot take a line until the pipeline prints a number which is equal to or bigger
+/// than the line number, so the lines after the last number must be kept.
+/// `tee` stops reading the input while the lines kept exceed `WINDOW_SIZE` and the main thread has
+/// resolved lines to take. Otherwise it keeps reading because the pipeline may need more lines
+/// to print the next number.
+pub struct Window {
+    state: Mutex<WindowState>,
+    cond: Condvar,
+}
+
+struct WindowState {
+    lines: VecDeque<Vec<u8>>,
+    bytes: usize,   // total size of `lines`
+    read: u64,      // number of lines read from the input
+    resolved: u64,  // the largest number printed by the pipeline so far
+    eof: bool,
+}
+
+impl Window {
+    fn new() -> Window {
+        Window {
+            state: Mutex::new(WindowState {
+                lines: VecDeque::new(),
+                bytes: 0,
+                read: 0,
+                resolved: 0,
+                eof: false,
+            }),
+            cond: Condvar::new(),
+        }
+    }
+
+    /// Wait until another line can be read
+    fn wait_room(&self) {
+        let mut state = self.state.lock().unwrap();
+        // The line which the main thread is working on
+        while state.bytes > WINDOW_SIZE && state.resolved >= state.read - state.lines.len() as u64 {
+            state = self.cond.wait(state).unwrap();
+        }
+    }
+
+    fn push(&self, line: Vec<u8>) {
+        let mut state = self.state.lock().unwrap();
+        state.bytes += line.len();
+        state.read += 1;
+        state.lines.push_back(line);
+        self.cond.notify_all();
+    }
+
+    fn close(&self) {
+        self.state.lock().unwrap().eof = true;
+        self.cond.notify_all();
+    }
+
+    /// Take the next line. Returns None at the end of the input.
+    fn take(&self) -> Option<Vec<u8>> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(line) = state.lines.pop_front() {
+                state.bytes -= line.len();
+                self.cond.notify_all();
+                return Some(line);
+            }
+            if state.eof {
+                return None;
+            }
+            state = self.cond.wait(state).unwrap();
+        }
+    }
+
+    /// The pipeline printed the number `n`
+    pub fn resolve(&self, n: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.resolved = state.resolved.max(n);
+        self.cond.notify_all();
+    }
+}
+
+/// Reader of the lines taken from the `Window`
+struct WindowReader {
+    window: Arc<Window>,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for WindowReader {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        while self.pos >= self.buf.len() {
+            match self.window.take() {
+                Some(b) => {
+                    self.buf = b;
+                    self.pos = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+        let n = out.len().min(self.buf.len() - self.pos);
+        out[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Pass the input to `pipeline_stdin` line by line, and return the reader of the identical input.
+/// The behavior is similar to tee(1) command but the lines are kept only while they are in the `Window`.
+/// `line_end` is line delimiter, generally it is supposed to be the new-line character like `\n`.
+///
+/// Example of duplicating standard input:
+/// ```ignore
+/// let (pipeline_stdin, numbers) = exec_pipeline("grep -n AAA", None).unwrap();
+/// let (window, stdin, _thread1) = tee(io::stdin(), b'\n', pipeline_stdin);
+/// ```
+/// => `stdin` reads identical data as `io::stdin()` while `grep` reads it as well.
+/// The numbers printed by the pipeline must be given to `window.resolve`.
+pub fn tee(
+    input: impl Read + Send + 'static,
+    line_end: u8,
+    pipeline_stdin: Box<dyn Write + Send>,
+) -> (Arc<Window>, Box<dyn Read + Send>, JoinHandle<()>) {
+    let window = Arc::new(Window::new());
+    let reader_window = Arc::clone(&window);
+    let handler = thread::spawn(move || {
+        let mut stdin = BufReader::new(input);
+        let mut writer = BufWriter::new(pipeline_stdin);
+        let mut buf = Vec::with_capacity(DEFAULT_CAP);
+        loop {
+            if stdin.buffer().is_empty() {
+                // Reading more may block. Let the pipeline see the lines so far.
+                let _ = writer.flush();
+            }
+            reader_window.wait_room();
+            buf.clear();
+            match stdin.read_until(line_end, &mut buf) {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(_) => {
+                    debug!("tee: Got error while loading from stdin");
+                    break;
+                }
+            };
+            // Ignore error because the command may not accept standard input (i.e seq command).
+            if writer.write_all(&buf).is_err() {
+                writer = BufWriter::new(Box::new(io::sink()));
+            }
+            reader_window.push(buf.clone());
+        }
+        // Close the standard input of the pipeline
+        drop(writer);
+        reader_window.close();
+    });
+    let reader = WindowReader {
+        window: Arc::clone(&window),
+        buf: Vec::new(),
+        pos: 0,
+    };
+    (window, Box::new(reader), handler)
+}
+
+/// Reader of a file which does not move the file offset.
+/// It reads the file independently of the process which shares the offset (-e).
+#[cfg(unix)]
+pub struct FileReader {
+    file: File,
+    pos: u64,
+}
+
+#[cfg(unix)]
+impl FileReader {
+    /// Start reading from the current offset of `file`
+    pub fn new(mut file: File) -> io::Result<FileReader> {
+        let pos = file.stream_position()?;
+        Ok(FileReader { file, pos })
+    }
+}
+
+#[cfg(unix)]
+impl Read for FileReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        use std::os::unix::fs::FileExt;
+        let n = self.file.read_at(buf, self.pos)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+/// Extract numbers and the regions of the lines from noisey strings.
+/// Up to `reorder` numbers are kept to sort them, and the same numbers in a row are sent once.
+/// The numbers are also given to `window` so that `tee` knows which lines are resolved.
+pub fn clean_numbers (
+    mut input: BufReader<Box<dyn Read + Send>>,
+    line_end: u8,
+    reorder: usize,
+    window: Option<Arc<Window>>,
+) -> (Receiver<(u64, Region)>, JoinHandle<()>) {
+    let (tx, rx) = mpsc::channel();
+    let handler = thread::spawn(move || {
+        debug!("clean_numbers: thread: start");
+        let mut heap = BinaryHeap::new();
+        let mut last = None;
+        // Returns false if the receiver is gone
+        let mut send = |n: (u64, Region)| {
+            if last == Some(n) {
+                return true;
+            }
+            last = Some(n);
+            if let Some(ref w) = window {
+                w.resolve(n.0);
+            }
+            tx.send(n).is_ok()
+        };
+        loop {
+            let mut buf = Vec::with_capacity(DEFAULT_CAP);
+            match input.read_until(line_end, &mut buf) {
+                Ok(0) => break,
+                Ok(_) => {},
+                Err(_) => break,
+            };
+            let line = String::from_utf8_lossy(&buf).to_string();
+            match stringutils::extract_region(line) {
+                Some(n) => {
+                    heap.push(Reverse(n));
+                    if heap.len() > reorder {
+                        let Reverse(n) = heap.pop().unwrap();
+                        if !send(n) {
+                            break;
+                        }
+                    }
+                },
+                None => {},
+            };
+        }
+        while let Some(Reverse(n)) = heap.pop() {
+            if !send(n) {
+                break;
+            }
+        }
+        drop(tx);
+        // No more numbers. All of the lines are resolved.
+        if let Some(ref w) = window {
+            w.resolve(u64::MAX);
+        }
+        debug!("clean_numbers: thread: end");
+    });
+    return (rx, handler)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    /// Writer which can be inspected after it is moved
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_tee() {
+        let input = b"AAA\nBBB\nCCC\n";
+        let pipeline_stdin = SharedBuf::default();
+        let (_window, mut stdin, thread1) = tee(&input[..], b'\n', Box::new(pipeline_stdin.clone()));
+        let mut received = Vec::new();
+        stdin.read_to_end(&mut received).unwrap();
+        thread1.join().unwrap();
+        assert_eq!(received, input);
+        assert_eq!(*pipeline_stdin.0.lock().unwrap(), input);
+    }
+
+    #[test]
+    fn test_tee_window() {
+        let input = b"AAA\n".repeat(WINDOW_SIZE);
+        let (window, _stdin, _thread1) = tee(std::io::Cursor::new(input), b'\n', Box::new(io::sink()));
+        // All of the lines are resolved. No more lines need to be read.
+        window.resolve(u64::MAX);
+        let start = Instant::now();
+        while window.state.lock().unwrap().bytes <= WINDOW_SIZE && start.elapsed() < Duration::from_secs(10) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        thread::sleep(Duration::from_millis(100));
+        let bytes = window.state.lock().unwrap().bytes;
+        assert!(WINDOW_SIZE < bytes && bytes <= WINDOW_SIZE + 4);
+    }
+
+    #[test]
+    fn test_tee_window_unresolved() {
+        let input = b"AAA\n".repeat(WINDOW_SIZE);
+        let (window, mut stdin, thread1) = tee(std::io::Cursor::new(input), b'\n', Box::new(io::sink()));
+        // The main thread waits for the number of the first line, which needs more lines to be read
+        stdin.read_exact(&mut [0; 4]).unwrap();
+        thread1.join().unwrap();
+        assert_eq!(window.state.lock().unwrap().read, WINDOW_SIZE as u64);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_file_reader() {
+        let path = std::env::temp_dir().join(format!("teip_test_file_reader_{}", std::process::id()));
+        std::fs::write(&path, b"AAA\nBBB\n").unwrap();
+        let mut file = File::open(&path).unwrap();
+        file.seek(io::SeekFrom::Start(4)).unwrap();
+        let mut reader = FileReader::new(file.try_clone().unwrap()).unwrap();
+        let mut received = Vec::new();
+        reader.read_to_end(&mut received).unwrap();
+        assert_eq!(received, b"BBB\n");
+        // The offset is not moved
+        assert_eq!(file.stream_position().unwrap(), 4);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_messy_numbers() {
+        cfg_if::cfg_if! {
+            if #[cfg(windows)] {
+                static SED_CMD: &str = "C:\\\"Program Files\"\\Git\\usr\\bin\\sed.exe";
+            } else {
+                static SED_CMD: &str = "sed";
+            }
+        }
+        let (mut tx, mut rx_messy_numbers) = exec_pipeline(&format!("{} s/./3/", SED_CMD), None).unwrap();
+        tx.write_all(b"abcdef\n").unwrap();
+        let mut buf = Vec::with_capacity(16);
+        drop(tx);
+        rx_messy_numbers.read_until(b'\n', &mut buf).unwrap();
+        let line = String::from_utf8_lossy(&buf).to_string();
+        assert_eq!(line, "3bcdef\n");
+    }
+
+    /// Example of noisey numbers (Read from BufReader):
+    /// ```
+    /// 1: test test"
+    /// 2-
+    ///     3@@@the line has spaces at beginning
+    ///     4!!!TAB character is also acceptable
+    /// 5
+    /// ```
+    /// Example of result (Receiver will get u64 numbers):
+    /// ```
+    /// 1
+    /// 2
+    /// 3
+    /// 4
+    /// 5
+    /// ```
+    #[test]
+    fn test_clean_numbers() {
+        cfg_if::cfg_if! {
+            if #[cfg(windows)] {
+                static SED_CMD: &str = "C:\\\"Program Files\"\\Git\\usr\\bin\\sed.exe";
+            } else {
+                static SED_CMD: &str = "sed";
+            }
+        }
+        let (mut tx, rx_messy_numbers) = exec_pipeline(&format!("{} s/./3/", SED_CMD), None).unwrap();
+        tx.write_all(b"abcdef\n").unwrap();
+        drop(tx);
+        let (numbers, _) = clean_numbers(rx_messy_numbers, b'\n', 0, None);
+        match numbers.recv() {
+            Ok(n) => {
+                assert_eq!(n, (3, Region::Line));
+            },
+            Err(_) => {
+                assert!(false);
+            },
+        };
+    }
+
+    #[test]
+    fn test_clean_numbers_reorder() {
+        let numbers = |reorder: usize| -> Vec<u64> {
+            let input: Box<dyn Read + Send> = Box::new(io::Cursor::new(b"3\n3\n1\n3\n2\n5\n4\n".to_vec()));
+            let (rx, _) = clean_numbers(BufReader::new(input), b'\n', reorder, None);
+            rx.iter().map(|(n, _)| n).collect()
+        };
+        // The same numbers in a row are sent once
+        assert_eq!(numbers(0), vec![3, 1, 3, 2, 5, 4]);
+        assert_eq!(numbers(3), vec![1, 2, 3, 4, 5]);
+        assert_eq!(numbers(usize::MAX), vec![1, 2, 3, 4, 5]);
+    }
+}
@@ -1,106 +1,56 @@
-use std::io::{self, BufRead};
-
 use onig;
 pub type Regex = onig::Regex;
 pub type RegexOptions = onig::RegexOptions;
 pub type Syntax = onig::Syntax;
 
-use super::super::{error_exit, errors, msg_error, stringutils, PipeIntercepter, DEFAULT_CAP};
-
-pub fn new_regex() -> Regex {
-    Regex::new("").unwrap()
-}
+use super::super::chunk::Index;
+use super::super::procs::match_spans;
+use super::super::selector::{Selector, Span};
 
-pub fn new_option_multiline_regex(s: &str) -> Regex {
-    Regex::with_options(s, RegexOptions::REGEX_OPTION_MULTILINE, Syntax::default())
-        .unwrap_or_else(|e| error_exit(&e.to_string()))
+pub fn new_option_multiline_regex(s: &str) -> Result<Regex, String> {
+    Regex::with_options(s, RegexOptions::REGEX_OPTION_MULTILINE, Syntax::default()).map_err(|e| e.to_string())
 }
 
-pub fn new_option_none_regex(s: &str) -> Regex {
-    Regex::with_options(s, RegexOptions::REGEX_OPTION_NONE, Syntax::default())
-        .unwrap_or_else(|e| error_exit(&e.to_string()))
+pub fn new_option_none_regex(s: &str) -> Result<Regex, String> {
+    Regex::with_options(s, RegexOptions::REGEX_OPTION_NONE, Syntax::default()).map_err(|e| e.to_string())
 }
 
 /// Bypassing multiple strings in a line based on Oniguruma Regular Expression ( -g -G -o )
-pub fn regex_onig_proc(
-    ch: &mut PipeIntercepter,
-    line: &Vec<u8>,
-    re: &Regex,
+pub struct OnigSelector {
+    re: Regex,
     invert: bool,
-) -> Result<(), errors::ChunkSendError> {
-    let line = String::from_utf8_lossy(&line).to_string();
-    let mut left_index = 0;
-    let mut right_index;
-    for cap in re.find_iter(&line) {
-        right_index = cap.0;
-        let unmatched = &line[left_index..right_index];
-        let matched = &line[cap.0..cap.1];
-        // Ignore empty string.
-        // Regex "*" matches empty, but , in most situations,
-        // handling empty string is not helpful for users.
-        if !unmatched.is_empty() {
-            if !invert {
-                ch.send_keep(unmatched.to_string())?;
-            } else {
-                ch.send_byps(unmatched.to_string())?;
-            }
-        }
-        if !invert {
-            ch.send_byps(matched.to_string())?;
-        } else {
-            ch.send_keep(matched.to_string())?;
-        }
-        left_index = cap.1;
+}
+
+impl OnigSelector {
+    pub fn new(re: Regex, invert: bool) -> OnigSelector {
+        OnigSelector { re, invert }
     }
-    if left_index < line.len() {
-        let unmatched = &line[left_index..line.len()];
-        if !invert {
-            ch.send_keep(unmatched.to_string())?;
-        } else {
-            ch.send_byps(unmatched.to_string())?;
-        }
+}
+
+impl Selector for OnigSelector {
+    fn select(&mut self, record: &str) -> Vec<Span> {
+        match_spans(self.re.find_iter(record), record.len(), self.invert)
     }
-    Ok(())
 }
 
 /// Bypassing particular lines based on Oniguruma Regular Expression ( -g -G )
-pub fn regex_onig_line_proc(
-    ch: &mut PipeIntercepter,
-    re: &Regex,
+pub struct OnigLineSelector {
+    re: Regex,
     invert: bool,
-    line_end: u8,
-) -> Result<(), errors::ChunkSendError> {
-    let stdin = io::stdin();
-    loop {
-        let mut buf = Vec::with_capacity(DEFAULT_CAP);
-        match stdin.lock().read_until(line_end, &mut buf) {
-            Ok(n) => {
-                let eol = stringutils::trim_eol(&mut buf);
-                if n == 0 {
-                    ch.send_eof()?;
-                    break;
-                }
-                let line = String::from_utf8_lossy(&buf).to_string();
-                match re.find(&line) {
-                    Some(_) => {
-                        if invert {
-                            ch.send_keep(line.to_string())?;
-                        } else {
-                            ch.send_byps(line.to_string())?;
-                        }
-                    }
-                    None => {
-                        if invert {
-                            ch.send_byps(line.to_string())?;
-                        } else {
-                            ch.send_keep(line.to_string())?;
-                        }
-                    }
-                };
-                ch.send_keep(eol)?;
-            }
-            Err(e) => msg_error(&e.to_string()),
+}
+
+impl OnigLineSelector {
+    pub fn new(re: Regex, invert: bool) -> OnigLineSelector {
+        OnigLineSelector { re, invert }
+    }
+}
+
+impl Selector for OnigLineSelector {
+    fn select(&mut self, record: &str) -> Vec<Span> {
+        if self.re.find(record).is_some() != self.invert {
+            vec![Span::new(0, record.len(), Index::None)]
+        } else {
+            vec![]
         }
     }
-    Ok(())
 }
@@ -0,0 +1,91 @@
+use wasi_common::sync::{add_to_linker, WasiCtxBuilder};
+use wasi_common::WasiCtx;
+use wasmtime::{Config, Engine, Linker, Memory, Module, Store, Trap, TypedFunc};
+use std::convert::TryFrom;
+
+/// Limits of the instructions executed for each hole, so that an endless loop stops with an error.
+/// The module is given `FUEL_PER_BYTE` for each byte of the hole in addition to `FUEL_PER_HOLE`.
+const FUEL_PER_HOLE: u64 = 100_000_000;
+const FUEL_PER_BYTE: u64 = 1_000;
+
+/// WebAssembly module which transforms the holes (--wasm)
+///
+/// The module is instantiated once with WASI (preview1) and has to export:
+///   memory                                   Linear memory to exchange the strings
+///   teip_alloc(len: i32) -> i32              Allocate `len` bytes and return the pointer
+///   teip_transform(ptr: i32, len: i32) -> i64
+///                                            Transform the `len` bytes at `ptr` and return the
+///                                            result as `(pointer << 32) | length`
+/// and may export:
+///   teip_reset()                             Free the memory allocated for the hole
+/// `_initialize` is called first if it is exported (WASI reactor). `teip_reset` is called after each hole,
+/// so `teip_alloc` may allocate the buffers one after another. Without it, `teip_alloc` has to reuse the buffer.
+/// The module cannot access files, environment variables or stdin/stdout. Only stderr is inherited.
+/// The calls for each hole stop with an error if they run out of the fuel (see `FUEL_PER_HOLE`).
+pub struct Plugin {
+    store: Store<WasiCtx>,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    transform: TypedFunc<(i32, i32), i64>,
+    reset: Option<TypedFunc<(), ()>>,
+}
+
+impl Plugin {
+    pub fn new(path: &str) -> Result<Plugin, String> {
+        let err = |e: wasmtime::Error| format!("{}: {:#}", path, e);
+        let engine = Engine::new(Config::new().consume_fuel(true)).map_err(err)?;
+        let module = Module::from_file(&engine, path).map_err(err)?;
+        let mut linker = Linker::new(&engine);
+        add_to_linker(&mut linker, |ctx: &mut WasiCtx| ctx).map_err(err)?;
+        let wasi = WasiCtxBuilder::new().inherit_stderr().build();
+        let mut store = Store::new(&engine, wasi);
+        store.set_fuel(FUEL_PER_HOLE).map_err(err)?;
+        let instance = linker.instantiate(&mut store, &module).map_err(err)?;
+        if let Ok(init) = instance.get_typed_func::<(), ()>(&mut store, "_initialize") {
+            init.call(&mut store, ()).map_err(err)?;
+        }
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| format!("{}: 'memory' is not exported", path))?;
+        let alloc = instance
+            .get_typed_func::<i32, i32>(&mut store, "teip_alloc")
+            .map_err(err)?;
+        let transform = instance
+            .get_typed_func::<(i32, i32), i64>(&mut store, "teip_transform")
+            .map_err(err)?;
+        let reset = instance.get_typed_func::<(), ()>(&mut store, "teip_reset").ok();
+        Ok(Plugin {
+            store,
+            memory,
+            alloc,
+            transform,
+            reset,
+        })
+    }
+
+    /// Pass the string in the hole to the module and get the result.
+    pub fn apply(&mut self, input: &str) -> Result<String, String> {
+        let err = |e: wasmtime::Error| match e.downcast_ref::<Trap>() {
+            Some(Trap::OutOfFuel) => "WebAssembly error: Too many instructions".to_string(),
+            _ => format!("WebAssembly error: {:#}", e),
+        };
+        let len = i32::try_from(input.len())
+            .map_err(|_| format!("WebAssembly error: The hole is too large ({} bytes)", input.len()))?;
+        let fuel = FUEL_PER_HOLE.saturating_add(FUEL_PER_BYTE.saturating_mul(input.len() as u64));
+        self.store.set_fuel(fuel).map_err(err)?;
+        let ptr = self.alloc.call(&mut self.store, len).map_err(err)?;
+        self.memory
+            .write(&mut self.store, ptr as usize, input.as_bytes())
+            .map_err(|e| err(e.into()))?;
+        let ret = self.transform.call(&mut self.store, (ptr, len)).map_err(err)? as u64;
+        let (out_ptr, out_len) = ((ret >> 32) as usize, (ret & 0xffff_ffff) as usize);
+        let mut buf = vec![0; out_len];
+        self.memory
+            .read(&self.store, out_ptr, &mut buf)
+            .map_err(|e| err(e.into()))?;
+        if let Some(ref reset) = self.reset {
+            reset.call(&mut self.store, ()).map_err(err)?;
+        }
+        Ok(String::from_utf8_lossy(&buf).to_string())
+    }
+}
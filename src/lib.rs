@@ -0,0 +1,66 @@
+//! Masking tape to help commands "do one thing well"
+//!
+//! teip divides the input into the chunks under the masking tape (Keep) and the chunks in
+//! the holes (Hole). This crate provides the selection engine of the `teip` command.
+//!
+//! ```
+//! use teip::TapeBuilder;
+//!
+//! // Same as `teip -og '\d+' -- sed 's/./@/g'`
+//! let mut tape = TapeBuilder::new().regex(r"\d+").only_matched(true).build().unwrap();
+//! let mut out = Vec::new();
+//! tape.transform(&b"ab12cd345\n"[..], &mut out, |hole, _| "@".repeat(hole.len())).unwrap();
+//! assert_eq!(out, b"ab@@cd@@@\n");
+//! ```
+//!
+//! See `Tape`, `TapeBuilder` and `Selector` for details.
+mod list {
+    pub mod converter;
+    pub mod ranges;
+}
+mod csv {
+    pub mod parser;
+}
+mod sed {
+    pub mod address;
+}
+mod predicate {
+    pub mod expr;
+}
+mod impure {
+    #[cfg(feature = "oniguruma")]
+    pub mod onig;
+}
+mod pure {
+    #[cfg(not(feature = "oniguruma"))]
+    pub mod onig;
+}
+mod chunk;
+mod exoffload;
+mod procs;
+mod selector;
+mod stringutils;
+mod tape;
+
+pub use chunk::{ChunkSendError, Emitter, Index, Location};
+pub use selector::{Selector, Span};
+pub use tape::{Event, Tape, TapeBuilder};
+
+#[macro_use]
+extern crate lazy_static;
+
+use regex::Regex;
+
+#[cfg(feature = "oniguruma")]
+use impure::onig;
+
+#[cfg(not(feature = "oniguruma"))]
+use pure::onig;
+
+/// Name of the command printed at the beginning of the messages
+pub const CMD: &str = env!("CARGO_PKG_NAME"); // "teip"
+const DEFAULT_CAP: usize = 1024;
+
+lazy_static! {
+    static ref REGEX_WS: Regex = Regex::new("\\s+").unwrap();
+}
@@ -1,64 +1,64 @@
-mod list {
-    pub mod converter;
-    pub mod ranges;
-}
-mod csv {
-    pub mod parser;
-}
 mod impure {
-    #[cfg(feature = "oniguruma")]
-    pub mod onig;
+    #[cfg(feature = "wasm")]
+    pub mod wasm;
 }
 mod pure {
-    #[cfg(not(feature = "oniguruma"))]
-    pub mod onig;
+    #[cfg(not(feature = "wasm"))]
+    pub mod wasm;
 }
-mod chunk;
-mod procs;
+mod builtin;
+mod diff;
 mod errors;
+mod pipeintercepter;
+mod report;
+mod script;
+mod sink;
 mod spawnutils;
+
 use errors::*;
-mod pipeintercepter;
 use pipeintercepter::PipeIntercepter;
-mod stringutils;
+use teip::{Emitter, Tape, TapeBuilder, CMD};
 
 #[macro_use]
 extern crate lazy_static;
 
+#[cfg(feature = "wasm")]
+use impure::wasm;
+
+#[cfg(not(feature = "wasm"))]
+use pure::wasm;
+
 use log::debug; // Enable with RUST_LOG=debug
-use regex::Regex;
 use std::env;
-use std::io::{self, BufRead};
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
 use structopt::StructOpt;
 
-#[cfg(feature = "oniguruma")]
-use impure::onig;
-
-#[cfg(not(feature = "oniguruma"))]
-use pure::onig;
-
-const CMD: &'static str = env!("CARGO_PKG_NAME"); // "teip"
 pub const DEFAULT_CAP: usize = 1024;
 
 lazy_static! {
-    static ref REGEX_WS: Regex = Regex::new("\\s+").unwrap();
     static ref DEFAULT_HIGHLIGHT: String = match env::var("TEIP_HIGHLIGHT") {
         Ok(v) => v,
         Err(_) => "\x1b[36m[\x1b[0m\x1b[01;31m{}\x1b[0m\x1b[36m]\x1b[0m".to_string(),
     };
-    static ref GREP_PATH: String = match env::var("TEIP_GREP_PATH") {
-        Ok(v) => v,
-        Err(_) => "grep".to_string(),
-    };
-    static ref SED_PATH: String = match env::var("TEIP_SED_PATH") {
+    // Highlight for every second hole, so that the adjacent holes can be told apart.
+    // It does not follow TEIP_HIGHLIGHT, otherwise the adjacent holes would look the same.
+    static ref ALT_HIGHLIGHT: String = match env::var("TEIP_HIGHLIGHT_ALT") {
         Ok(v) => v,
-        Err(_) => "sed".to_string(),
+        Err(_) => "\x1b[36m[\x1b[0m\x1b[01;33m{}\x1b[0m\x1b[36m]\x1b[0m".to_string(),
     };
+    pub static ref HL: Vec<&'static str> = DEFAULT_HIGHLIGHT.split("{}").collect();
+    pub static ref HL_ALT: Vec<&'static str> = ALT_HIGHLIGHT.split("{}").collect();
     static ref AWK_PATH: String = match env::var("TEIP_AWK_PATH") {
         Ok(v) => v,
         Err(_) => "awk".to_string(),
     };
-    static ref HL: Vec<&'static str> = DEFAULT_HIGHLIGHT.split("{}").collect();
+    static ref SHELL_PATH: String = match env::var("TEIP_SHELL") {
+        Ok(v) => v,
+        Err(_) => if cfg!(windows) { "cmd".to_string() } else { "sh".to_string() },
+    };
 }
 
 #[derive(StructOpt, Debug)]
@@ -69,23 +69,44 @@ lazy_static! {
   teip -g <pattern> [-Gosvz] [--] [<command>...]
   teip -c <list> [-svz] [--] [<command>...]
   teip -l <list> [-svz] [--] [<command>...]
+  teip --sed <address> [-svz] [--] [<command>...]
+  teip --where <expression> [-d <delimiter> | -D <pattern> | --csv] [-svz] [--] [<command>...]
   teip -f <list> [-d <delimiter> | -D <pattern> | --csv] [-svz] [--] [<command>...]
+  teip -f <list> [...] [--] <command>... ::: -f <list> [--] <command>...
   teip -e <string> [-svz] [--] [<command>...]
+  teip [-i[SUFFIX]] <any of above> [--] [<command>...] :::: <file>...
 
 OPTIONS:
     -g <pattern>        Act on lines that match the regular expression <pattern>.
         -o              -g acts on only matched parts.
         -G              -g interprets Oniguruma regular expressions.
+        -A <number>     -g acts on <number> lines after the matched lines as well.
+        -B <number>     -g acts on <number> lines before the matched lines as well.
+        -C <number>     Same as -A <number> -B <number>.
     -c <list>           Act on these characters.
     -l <list>           Act on these lines.
+    --sed <address>     Act on lines selected by the address of sed (e.g. '/BEGIN/,/END/',
+                        '1~3', '$!'), which is evaluated without sed.
+    --where <expression>
+                        Act on only lines which satisfy <expression> like
+                        '$3 > 500 && $7 ~ /POST/'. The fields are split as -f does.
+                        Combined with another option above, it acts on those lines.
     -f <list>           Act on these white-space separated fields.
         -d <delimiter>  Use <delimiter> for the field delimiter of -f.
         -D <pattern>    Use regular expression <pattern> for the field delimiter of -f.
         --csv           -f interprets <list> as field numbers of a CSV according to
                         RFC 4180, instead of whitespace separated fields.
+        ::: -f <list> [--] <command>...
+                        Act on another <list> of fields with another <command>.
+                        Can be repeated.
     -e <string>         Execute <string> on another process that will receive identical
                         standard input as the main teip aommane, emitting numbers to be
-                        used as line numbers for actioning.
+                        used as line numbers for actioning. '<N>:<start>-<end>',
+                        '<N>:b<start>-<end>' and '<N>:f<K>' act on the characters,
+                        bytes and field of the line instead.
+        --reorder <size>
+                        Sort up to <size> numbers printed by -e before using them,
+                        or all of them after <string> finishes with 'all'.
 
 FLAGS:
     -h, --help          Prints help information.
@@ -95,17 +116,70 @@ FLAGS:
                         trailing newlines.
     -I  <replace-str>   Replace the <replace-str> with the actioned chunk in <command>,
                         implying -s.
+    -S, --shell         Execute <command> as a single string with the shell.
+    --collect           Execute <command> once over all of the actioned chunks after
+                        reading the entire input, and put the results back in order.
+                        <command> must print a line for each chunk (e.g. sort, tac).
+    --line-buffered     Print the output at each line end instead of buffering it.
+                        Enabled by default if the standard output is a terminal.
+    --queue-size <size> Maximum size of the input kept in memory while <command> is
+                        processing it, in bytes. K, M and G suffixes are allowed
+                        (default: 16M).
+    --pty               Attach the standard output of <command> to a pseudo-terminal
+                        so that <command> flushes it line by line (e.g. grep, awk).
+    --builtin <name>[:<args>]
+                        Transform the actioned chunks with the built-in function
+                        instead of <command>, without spawning any process.
+                        upper, lower, trim, mask[:<char>], redact[:<number>],
+                        base64-encode, base64-decode, hex-encode, hex-decode,
+                        url-encode, url-decode, hash[:sha224|sha256|sha384|sha512],
+                        counter[:<start>[:<step>]], constant:<string>
+    --script <script>   Transform the actioned chunks with the Rhai <script>
+                        instead of <command>, without spawning any process.
+                        The chunk is given as `x`, and its location as `nr`, `seq`,
+                        `offset`, `field` and `match_no`.
+    --script-file <file>
+                        Same as --script but read the script from <file>.
+    --wasm <file>       Transform the actioned chunks with the WebAssembly (WASI)
+                        module <file> instead of <command>. Requires the build
+                        with `--features wasm`.
+    --sink <kind>:<argument>
+                        Transform the actioned chunks with the backend instead of
+                        <command>. builtin:<name>[:<args>], script:<file>,
+                        wasm:<file> (same as the options above), or
+                        socket:<path> which sends each chunk to the Unix domain
+                        socket and reads a line as the result.
+    --dry-run-format <format>
+                        Print the holes in <format> instead of highlighting them when
+                        <command> is not given. 'highlight' (default) or 'json', which
+                        prints a JSON object for each hole.
+    --color <when>      Color the highlighted holes. 'auto' (default) colors them
+                        only if the output is a terminal and NO_COLOR is not set,
+                        'always' or 'never'.
+    --hole-index        Prefix the highlighted holes with their numbers like [1:foo].
     -v                  Invert the range of actioning.
     -z                  Line delimiter is NUL instead of a newline.
+    :::: <file>...      Read the files instead of the standard input. The lines are
+                        counted from the beginning of each file.
+        --files <file>  Read the files listed in <file> line by line as well.
+                        '-' reads the list from the standard input.
+        --continuous    Read the files as a single input, as if they were concatenated.
+    -i[SUFFIX]          Edit the files in place instead of printing the output.
+                        The original files are kept with [SUFFIX] if given.
+    --diff              Print the unified diff between the input and the output
+                        instead of the output, for each file if files are given.
+    --ignore-child-status
+                        Exit with 0 even if the targeted command fails.
 
 ALIASES:
-    -g <pattern>
-        -A <number>     Alias of -e 'grep -n -A <number> <pattern>'
-        -B <number>     Alias of -e 'grep -n -B <number> <pattern>'
-        -C <number>     Alias of -e 'grep -n -C <number> <pattern>'
-    --sed <pattern>     Alias of -e 'sed -n \"<pattern>=\"'
     --awk <pattern>     Alias of -e 'awk \"<pattern>{print NR}\"'
 
+EXIT STATUS:
+    0                   Success.
+    1                   Error in teip itself.
+    123                 The targeted command exited with non-zero status.
+    125                 The targeted command was terminated by a signal.
+
 EXAMPLES:
   Replace 'WORLD' with 'EARTH' on lines containing 'HELLO':
     $ cat file | teip -g HELLO -- sed 's/WORLD/EARTH/'
@@ -142,22 +216,62 @@ struct Args {
     solid: bool,
     #[structopt(short = "I", help = "Replace the <replace-str> with the actioned chunk in <command>, implying -s.")]
     replace: Option<String>,
+    #[structopt(short = "S", long = "shell", help = "Execute <command> as a single string with the shell")]
+    shell: bool,
+    #[structopt(long = "builtin", help = "Transform the chunks with the built-in function <name>[:<args>] instead of <command>")]
+    builtin: Option<String>,
+    #[structopt(long = "script", help = "Transform the chunks with the Rhai <script> instead of <command>")]
+    script: Option<String>,
+    #[structopt(long = "script-file", help = "Same as --script but read the script from <file>")]
+    script_file: Option<String>,
+    #[structopt(long = "wasm", help = "Transform the chunks with the WebAssembly module <file> instead of <command>")]
+    wasm: Option<String>,
+    #[structopt(long = "sink", help = "Transform the chunks with the backend <kind>:<argument> instead of <command>")]
+    sink: Option<String>,
+    #[structopt(long = "collect", help = "Execute <command> once over all of the chunks and put the results back in order")]
+    collect: bool,
+    #[structopt(long = "line-buffered", help = "Print the output at each line end instead of buffering it")]
+    line_buffered: bool,
+    #[structopt(long = "queue-size", parse(try_from_str = parse_size), help = "Maximum size of the input kept in memory while <command> is processing it")]
+    queue_size: Option<usize>,
+    #[structopt(long = "pty", help = "Attach the standard output of <command> to a pseudo-terminal to avoid buffering")]
+    pty: bool,
     #[structopt(long = "chomp", help = "The command spawned by -s receives the standard input without trailing newlines")]
     solid_chomp: bool,
     #[structopt(short = "v", help = "Invert the range of actioning")]
     invert: bool,
     #[structopt(short = "z", help = "Line delimiter is NUL instead of a newline")]
     zero: bool,
+    #[structopt(long = "files", help = "Read the files listed in <file> as well as the files given after '::::'")]
+    files_from: Option<String>,
+    #[structopt(long = "continuous", help = "Read the files as a single input, as if they were concatenated")]
+    continuous: bool,
+    #[structopt(long = "dry-run-format", possible_values = &["highlight", "json"], help = "Print the holes in <format> instead of highlighting them when <command> is not given")]
+    dry_run_format: Option<String>,
+    #[structopt(long = "color", possible_values = &["auto", "always", "never"], default_value = "auto", help = "Color the highlighted holes")]
+    color: String,
+    #[structopt(long = "hole-index", help = "Prefix the highlighted holes with their numbers")]
+    hole_index: bool,
+    #[structopt(long = "diff", help = "Print the unified diff between the input and the output instead of the output")]
+    diff: bool,
+    #[structopt(short = "i", min_values = 0, max_values = 1, require_equals = true, help = "Edit the files in place, keeping the originals with [SUFFIX] if given")]
+    in_place: Option<Option<String>>,
+    #[structopt(long = "ignore-child-status", help = "Exit with 0 even if the targeted command fails")]
+    ignore_child_status: bool,
     #[structopt(short = "e", help = "Execute <string> on another process that will receive identical standard input as the main teip aommane, emitting numbers to be used as line numbers for actioning")]
     exoffload_pipeline: Option<String>,
-    #[structopt(short = "A", help = "Alias of -e 'grep -n -A <number> <pattern>'")]
+    #[structopt(long = "reorder", parse(try_from_str = parse_reorder), help = "Sort up to <size> numbers printed by -e before using them, or all of them with 'all'")]
+    reorder: Option<usize>,
+    #[structopt(short = "A", help = "-g acts on <number> lines after the matched lines as well")]
     after: Option<usize>,
-    #[structopt(short = "B", help = "Alias of -e 'grep -n -B <number> <pattern>'")]
+    #[structopt(short = "B", help = "-g acts on <number> lines before the matched lines as well")]
     before: Option<usize>,
-    #[structopt(short = "C", help = "Alias of -e 'grep -n -C <number> <pattern>'" )]
+    #[structopt(short = "C", help = "Same as -A <number> -B <number>" )]
     center: Option<usize>,
-    #[structopt(long = "sed", help = "Alias of -e 'sed -n \"<pattern>=\"'")]
+    #[structopt(long = "sed", help = "Act on lines selected by the address of sed")]
     sed: Option<String>,
+    #[structopt(long = "where", help = "Act on only lines which satisfy <expression> like '$3 > 500 && $7 ~ /POST/'")]
+    predicate: Option<String>,
     #[structopt(long = "awk", help = "Alias of -e 'awk \"<pattern>{print NR}\"'")]
     awk: Option<String>,
     #[structopt(long = "completion")]
@@ -170,42 +284,40 @@ fn main() {
     env_logger::init();
 
     // ***** Parse options and prepare configures *****
-    let args: Args = Args::from_args();
+    let args: Args = Args::from_iter(attach_suffix(env::args_os()));
 
     debug!("{:?}", args);
 
     if HL.len() < 2 {
         error_exit("Invalid format in TEIP_HIGHLIGHT variable")
     }
+    if HL_ALT.len() < 2 {
+        error_exit("Invalid format in TEIP_HIGHLIGHT_ALT variable")
+    }
 
-    let flag_zero = args.zero;
-    let cmds = args.commands;
-    let flag_only = args.only_matched;
-    let mut flag_regex = args.regex.is_some();
-    let flag_onig = args.onig_enabled;
+    let (cmds, extra_targets, mut files) = split_targets(&args.commands)
+        .unwrap_or_else(|e| error_exit(&e));
+    if let Some(ref list) = args.files_from {
+        files.extend(read_file_list(list).unwrap_or_else(|e| error_exit(&format!("{}: {}", list, e))));
+    }
+    let flag_files = !files.is_empty() || args.files_from.is_some();
+    let flag_regex = args.regex.is_some();
     let mut flag_solid = args.solid;
     let flag_solid_chomp = args.solid_chomp;
     let flag_replace = args.replace.is_some();
-    let flag_invert = args.invert;
+    let flag_ignore_child_status = args.ignore_child_status;
     let flag_char = args.char.is_some();
     let flag_lines = args.line.is_some();
+    let flag_sed = args.sed.is_some();
+    let flag_where = args.predicate.is_some();
     let flag_field = args.list.is_some();
-    let flag_delimiter = args.delimiter.is_some();
     let flag_csv = args.csv;
-    let delimiter = args.delimiter.as_ref().map(|s| s.as_str()).unwrap_or("");
-    let flag_regex_delimiter = args.regexp_delimiter.is_some();
+    let flag_in_place = args.in_place.is_some();
     let mut flag_exoffload = args.exoffload_pipeline.is_some();
     let mut exoffload_pipeline = args.exoffload_pipeline.as_ref().map(|s| s.as_str()).unwrap_or("");
 
-    let mut regex_mode = String::new();
-    let mut regex_compiled = Regex::new("").unwrap();
-    let mut onig_regex_raw = &String::new();
-    let mut onig_regex_compiled = onig::new_regex();
-    let mut line_end = b'\n';
-    let mut process_each_line = true; // true if single hole is always coveres entire line
-    let mut ch: PipeIntercepter;
+    let mut tape = TapeBuilder::new();
     let mut flag_dryrun = true;
-    let regex_delimiter;
 
     if let Some(shell) = args.completion {
         use structopt::clap::Shell;
@@ -227,201 +339,432 @@ fn main() {
         u();
     }
 
-    // If any of -A, -B, -C is specified, set -e option and set regex flag off
-    //   "-A 1 -g pattern" => "-e 'grep -A 1 pattern'"
-    //   "-B 1 -g pattern" => "-e 'grep -B 1 pattern'"
-    //   "-C 1 -g pattern" => "-e 'grep -C 1 pattern'"
-    let mut grep_args = vec![GREP_PATH.to_string(), "-n".to_string()];
+    // If any of -A, -B, -C is specified with -g, act on the lines around the matched lines as well
+    //   "-A 1 -B 2 -g pattern" => 2 lines before and 1 line after the matched lines
+    //   "-C 1 -A 3 -g pattern" => -A and -B take priority over -C
     let pipeline;
     if ( args.after.is_some() || args.before.is_some() || args.center.is_some() ) && flag_regex {
-        if let Some(n) = args.after {
-            grep_args.push("-A".to_string());
-            grep_args.push(n.to_string());
-        } else if let Some(n) = args.before {
-            grep_args.push("-B".to_string());
-            grep_args.push(n.to_string());
-        } else if let Some(n) = args.center {
-            grep_args.push("-C".to_string());
-            grep_args.push(n.to_string());
-        }
+        let before = args.before.or(args.center).unwrap_or(0);
+        let after = args.after.or(args.center).unwrap_or(0);
         if let Some(ref pattern) = args.regex {
-            grep_args.push(pattern.to_string());
+            tape.regex(pattern).context(before, after);
         }
-        flag_exoffload = true;
-        flag_regex = false;
-        pipeline = grep_args.join(" ");
-        exoffload_pipeline = &pipeline;
-    } else if let Some(ref pattern) = args.sed {
-        // --sed option
-        flag_exoffload = true;
-        pipeline = format!("{} -n '{}='", SED_PATH.as_str(), pattern);
-        exoffload_pipeline = &pipeline;
+    } else if let Some(ref address) = args.sed {
+        tape.sed(address);
     } else if let Some(ref pattern) = args.awk {
         // --awk option
         flag_exoffload = true;
         pipeline = format!("{} '{}{{print NR}}'", AWK_PATH.as_str(), pattern);
         exoffload_pipeline = &pipeline;
-    }
-
-    // -G switches regex mode
-    if flag_onig && flag_regex {
-        flag_regex = false;
-        onig_regex_raw = args.regex.as_ref().unwrap();
+    } else if let Some(ref pattern) = args.regex {
+        tape.regex(pattern);
     }
 
     // If any mandatory flags is not enabled, show help and exit.
     if !( flag_exoffload ||
           flag_regex     ||
-          flag_onig      ||
+          flag_sed       ||
+          flag_where     ||
           flag_field     ||
           flag_char      ||
           flag_lines )
-        // Even though --csv is specified, -f is not specified, show help and exit.
-        || ( flag_csv && !flag_field)
+        // Even though --csv is specified, none of -f, --where and -e is specified, show help and exit.
+        || ( flag_csv && !(flag_field || flag_where || flag_exoffload))
     {
         Args::clap().print_help().unwrap();
         std::process::exit(1);
     }
 
-    // Parse argument of -c option if specified
-    let char_list = args
-        .char
-        .as_ref()
-        .and_then(|s| {
-            list::converter::to_ranges(s.as_str(), flag_invert)
-                .map_err(|e| error_exit(&e.to_string()))
-                .ok()
-        })
-        .unwrap_or_else(|| list::converter::to_ranges("1", true).unwrap());
-
-    // Parse argument of -f option if specified
-    let field_list = args
-        .list
-        .as_ref()
-        .and_then(|s| {
-            list::converter::to_ranges(s.as_str(), flag_invert)
-                .map_err(|e| error_exit(&e.to_string()))
-                .ok()
-        })
-        .unwrap_or_else(|| list::converter::to_ranges("1", true).unwrap());
-
-    // Parse argument of -l option if specified
-    let line_list = args
-        .line
-        .as_ref()
-        .and_then(|s| {
-            list::converter::to_ranges(s.as_str(), flag_invert)
-                .map_err(|e| error_exit(&e.to_string()))
-                .ok()
-        })
-        .unwrap_or_else(|| list::converter::to_ranges("1", true).unwrap());
-
-    // If -z option is specified, change regex mode and line end
-    if flag_zero {
-        regex_mode = "(?ms)".to_string();
-        line_end = b'\0';
-    }
-
-    if flag_regex {
-        // Use default regex engine
-        regex_compiled =
-            Regex::new(&(regex_mode.to_owned() + args.regex.as_ref().unwrap_or(&"".to_owned())))
-                .unwrap_or_else(|e| error_exit(&e.to_string()));
-    }
-
-    if flag_onig {
-        // If -G option is specified, change regex engine
-        if flag_zero {
-            onig_regex_compiled =
-                onig::new_option_multiline_regex(onig_regex_raw);
-        } else {
-            onig_regex_compiled = onig::new_option_none_regex(onig_regex_raw);
+    if flag_exoffload {
+        tape.external(exoffload_pipeline);
+    }
+    if let Some(size) = args.reorder {
+        if !flag_exoffload {
+            error_exit("--reorder requires -e or --awk")
         }
+        tape.reorder(size);
+    }
+    if let Some(ref list) = args.char {
+        tape.chars(list);
+    }
+    if let Some(ref list) = args.line {
+        tape.lines(list);
+    }
+    if let Some(ref list) = args.list {
+        tape.fields(list);
+    }
+    if let Some(ref expr) = args.predicate {
+        tape.predicate(expr);
+    }
+    if let Some(ref delimiter) = args.delimiter {
+        tape.delimiter(delimiter);
+    }
+    if let Some(ref pattern) = args.regexp_delimiter {
+        tape.regex_delimiter(pattern);
     }
 
-    // If -D option is specified, compile regex delimiter
-    if flag_regex_delimiter {
-        regex_delimiter =
-            Regex::new(&(regex_mode.to_string() + args.regexp_delimiter.as_ref().unwrap()))
-                .unwrap_or_else(|e| error_exit(&e.to_string()));
-    } else {
-        regex_delimiter = REGEX_WS.clone();
+    // Parse arguments of -f options after ':::' if specified
+    if !extra_targets.is_empty() && (!flag_field || cmds.is_empty()) {
+        error_exit("':::' requires -f <list> and <command> before it")
+    }
+    for (list, _) in extra_targets.iter() {
+        tape.fields(list);
+    }
+
+    // --sink, --builtin, --script and --wasm transform the holes instead of <command>
+    let script_src = match (args.script, args.script_file) {
+        (Some(src), None) => Some(src),
+        (None, Some(path)) => Some(
+            std::fs::read_to_string(&path).unwrap_or_else(|e| error_exit(&format!("{}: {}", path, e))),
+        ),
+        (None, None) => None,
+        (Some(_), Some(_)) => error_exit("--script cannot be used with --script-file"),
+    };
+    let sink_args = (args.sink, args.builtin, script_src, args.wasm);
+    let flag_sink = sink_args != (None, None, None, None);
+    if flag_sink && (!cmds.is_empty() || flag_replace) {
+        error_exit("--sink, --builtin, --script and --wasm cannot be used with <command> or -I")
+    }
+    if args.collect && (flag_solid || flag_replace || flag_sink) {
+        error_exit("--collect cannot be used with -s, -I, --sink, --builtin, --script or --wasm")
+    }
+    if args.pty && (flag_solid || flag_replace || flag_sink || args.collect) {
+        error_exit("--pty cannot be used with -s, -I, --collect, --sink, --builtin, --script or --wasm")
     }
 
     // If no command is specified, set dryrun mode
-    if cmds.len() > 0 {
+    if !cmds.is_empty() || flag_sink {
         flag_dryrun = false;
     }
-
-    if (!flag_only && flag_regex) || flag_lines || flag_exoffload || flag_csv {
-        // The process requires to process whole stdin, not line by line
-        process_each_line = false;
+    if flag_in_place && (flag_dryrun || !flag_files) {
+        error_exit("-i requires <command> (or --sink, --builtin, --script, --wasm) and files after '::::' or --files")
+    }
+    if args.diff && (flag_dryrun || flag_in_place || args.zero) {
+        error_exit("--diff requires <command> (or --sink, --builtin, --script, --wasm), and cannot be used with -i or -z")
+    }
+    if args.dry_run_format.is_some() && !flag_dryrun {
+        error_exit("--dry-run-format cannot be used with <command>, --sink, --builtin, --script or --wasm")
+    }
+    if args.hole_index && !flag_dryrun {
+        error_exit("--hole-index cannot be used with <command>, --sink, --builtin, --script or --wasm")
+    }
+    if args.continuous && (flag_in_place || !flag_files) {
+        error_exit("--continuous requires files after '::::' or --files, and cannot be used with -i")
+    }
+    let mut target_cmds = vec![cmds];
+    target_cmds.extend(extra_targets.into_iter().map(|(_, c)| c));
+    if args.shell && !flag_dryrun {
+        // -S option: "sed 's/a/b/' | tr a-z A-Z" => sh -c "sed 's/a/b/' | tr a-z A-Z"
+        // The following arguments are quoted: printf '%s\n' 'a b' => sh -c "printf '%s\n' 'a b'"
+        for c in target_cmds.iter_mut() {
+            *c = spawnutils::shell_cmds(&SHELL_PATH, c);
+        }
     }
 
-    if flag_replace {
-        // If -I option is specified, enable -s option
+    if flag_replace || flag_sink {
+        // If -I, --sink, --builtin, --script or --wasm option is specified, enable -s option
         flag_solid = true;
     }
-    if flag_solid {
-        ch =
-            PipeIntercepter::start_solid_output(cmds, line_end, flag_dryrun, flag_solid_chomp, args.replace)
-                .unwrap_or_else(|e| error_exit(&e.to_string()));
-    } else {
-        ch = PipeIntercepter::start_output(cmds, line_end, flag_dryrun)
-            .unwrap_or_else(|e| error_exit(&e.to_string()));
-    }
+    let mut tape = tape
+        .only_matched(args.only_matched)
+        .onig(args.onig_enabled)
+        .csv(flag_csv)
+        .invert(args.invert)
+        .zero(args.zero)
+        .solid(flag_solid)
+        .build()
+        .unwrap_or_else(|e| error_exit(&e));
+    let line_end = tape.line_end();
+    let line_buffered = args.line_buffered || (!flag_in_place && io::stdout().is_terminal());
+    // NO_COLOR: https://no-color.org/
+    let flag_color = match args.color.as_str() {
+        "always" => true,
+        "never" => false,
+        _ => io::stdout().is_terminal() && env::var_os("NO_COLOR").map_or(true, |v| v.is_empty()),
+    };
+    let flag_hole_index = args.hole_index;
+    let highlight = flag_dryrun.then(|| sink::HighlightSink::new(flag_color, flag_hole_index));
+    let (flag_collect, flag_pty, queue_size, replace_str) = (args.collect, args.pty, args.queue_size, args.replace);
+    let shell = (args.shell && !flag_dryrun).then(|| SHELL_PATH.to_string());
+
+    // Spawn the targeted commands and print the output to `output`
+    let start = |output: Box<dyn Write + Send>| {
+        let hole_sink = match sink_args {
+            (Some(ref spec), None, None, None) => Some(sink::parse(spec, line_end)),
+            (None, Some(ref spec), None, None) => Some(sink::builtin(spec)),
+            (None, None, Some(ref src), None) => Some(sink::script(src)),
+            (None, None, None, Some(ref path)) => Some(sink::wasm(path)),
+            (None, None, None, None) => None,
+            _ => error_exit("Only one of --sink, --builtin, --script and --wasm can be specified"),
+        };
+        let mut ch = if let Some(s) = hole_sink {
+            PipeIntercepter::start(s.unwrap_or_else(|e| error_exit(&e)), line_end, output)
+        } else if flag_collect && !flag_dryrun {
+            let s = sink::CollectSink::new(target_cmds.clone(), line_end, flag_ignore_child_status);
+            PipeIntercepter::start(Box::new(s), line_end, output)
+        } else if flag_solid {
+            let (replace, shell) = (replace_str.clone(), shell.clone());
+            PipeIntercepter::start_solid_output(target_cmds.clone(), line_end, highlight, flag_solid_chomp, replace, shell, output)
+                .unwrap_or_else(|e| error_exit(&e.to_string()))
+        } else {
+            let cmds = target_cmds.clone();
+            PipeIntercepter::start_output(cmds, line_end, highlight, flag_ignore_child_status, flag_pty, output)
+                .unwrap_or_else(|e| error_exit(&e.to_string()))
+        };
+        ch.line_buffered(line_buffered);
+        if let Some(size) = queue_size {
+            ch.queue_size(size);
+        }
+        ch
+    };
 
     // ***** Start processing *****
-    if process_each_line {
-        let stdin = io::stdin();
-        loop {
-            let mut buf = Vec::with_capacity(DEFAULT_CAP);
-            match stdin.lock().read_until(line_end, &mut buf) {
-                Ok(0) => {
-                    ch.send_eof().unwrap_or_else(|e| msg_error(&e.to_string()));
-                    break;
-                }
-                Ok(_) => {},
-                Err(e) => msg_error(&e.to_string()),
-            };
-            let eol = stringutils::trim_eol(&mut buf);
-            if flag_regex {
-                procs::regex_proc(&mut ch, &buf, &regex_compiled, flag_invert)
-                    .unwrap_or_else(|e| error_exit(&e.to_string()));
-            } else if flag_onig {
-                onig::regex_onig_proc(&mut ch, &buf, &onig_regex_compiled, flag_invert)
-                    .unwrap_or_else(|e| error_exit(&e.to_string()));
-            } else if flag_char {
-                procs::char_proc(&mut ch, &buf, &char_list)
-                    .unwrap_or_else(|e| error_exit(&e.to_string()));
-            } else if flag_field && flag_delimiter {
-                procs::field_proc(&mut ch, &buf, delimiter, &field_list)
-                    .unwrap_or_else(|e| error_exit(&e.to_string()));
-            } else if flag_field {
-                procs::field_regex_proc(&mut ch, &buf, &regex_delimiter, &field_list)
-                    .unwrap_or_else(|e| error_exit(&e.to_string()));
-            }
-            ch.send_keep(eol)
-                .unwrap_or_else(|e| msg_error(&e.to_string()));
+    if let Some(ref suffix) = args.in_place {
+        for path in files.iter() {
+            edit_in_place(Path::new(path), suffix.as_deref(), &mut tape, &start, flag_ignore_child_status);
+        }
+        return;
+    }
+    // --diff and --dry-run-format json print the report of each file instead of the output
+    let flag_json = args.dry_run_format.as_deref() == Some("json");
+    let flag_diff = args.diff;
+    let report = |ch: &mut PipeIntercepter, path: &str| {
+        if flag_diff {
+            ch.diff(path)
+        } else if flag_json {
+            ch.json(path)
+        } else {
+            Ok(())
         }
+    };
+    let mut ch = start(Box::new(io::stdout()));
+    if !flag_files || args.continuous {
+        report(&mut ch, "-").unwrap_or_else(|e| abort(&mut ch, &e.to_string(), flag_ignore_child_status));
+    }
+    if !flag_files {
+        match stdin_file() {
+            Some(file) => tape.feed_file(file, &mut ch),
+            None => tape.feed(io::stdin(), &mut ch),
+        }
+        .unwrap_or_else(|e| abort(&mut ch, &e.to_string(), flag_ignore_child_status));
+    } else if args.continuous {
+        let mut input: Box<dyn Read + Send> = Box::new(io::empty());
+        for path in files.iter() {
+            let file = open_input(path).unwrap_or_else(|e| abort(&mut ch, &e, flag_ignore_child_status));
+            input = Box::new(input.chain(file));
+        }
+        tape.feed(input, &mut ch)
+            .unwrap_or_else(|e| abort(&mut ch, &e.to_string(), flag_ignore_child_status));
     } else {
-        if flag_lines {
-            procs::line_line_proc(&mut ch, &line_list, line_end)
-                .unwrap_or_else(|e| error_exit(&e.to_string()));
-        } else if flag_regex {
-            if flag_onig {
-                onig::regex_onig_line_proc(&mut ch, &onig_regex_compiled, flag_invert, line_end)
-                    .unwrap_or_else(|e| error_exit(&e.to_string()));
-            } else {
-                procs::regex_line_proc(&mut ch, &regex_compiled, flag_invert, line_end)
-                    .unwrap_or_else(|e| error_exit(&e.to_string()));
+        // The selection and the locations of the holes start over at each file
+        for path in files.iter() {
+            let file = open_input(path).unwrap_or_else(|e| abort(&mut ch, &e, flag_ignore_child_status));
+            report(&mut ch, path).unwrap_or_else(|e| abort(&mut ch, &e.to_string(), flag_ignore_child_status));
+            tape.feed_file_part(file, &mut ch)
+                .unwrap_or_else(|e| abort(&mut ch, &format!("{}: {}", path, e), flag_ignore_child_status));
+        }
+        ch.send_eof()
+            .unwrap_or_else(|e| abort(&mut ch, &e.to_string(), flag_ignore_child_status));
+    }
+
+    // ***** Reap the targeted commands *****
+    let failures = ch.wait();
+    if !failures.is_empty() && !flag_ignore_child_status {
+        child_failure_exit(&failures);
+    }
+}
+
+/// Give the suffix of -i[SUFFIX] as -i=SUFFIX so that -i never takes the next argument.
+///   ["teip", "-i.bak", "-l", "1"] => ["teip", "-i=.bak", "-l", "1"]
+fn attach_suffix<I: Iterator<Item = OsString>>(args: I) -> Vec<OsString> {
+    let mut options = true;
+    args.map(|arg| {
+        let attached = match arg.to_str() {
+            Some("--") => {
+                options = false;
+                None
+            }
+            Some(a) if options && a.starts_with("-i") && a.len() > 2 && !a.starts_with("-i=") => {
+                Some(format!("-i={}", &a[2..]))
             }
-        } else if flag_exoffload {
-            procs::exoffload_proc(&mut ch, exoffload_pipeline, flag_invert, line_end)
-                    .unwrap_or_else(|e| error_exit(&e.to_string()));
-        } else if flag_csv {
-            procs::csv_proc(&mut ch, &field_list, line_end, flag_solid)
-                    .unwrap_or_else(|e| error_exit(&e.to_string()));
+            _ => None,
+        };
+        attached.map(OsString::from).unwrap_or(arg)
+    })
+    .collect()
+}
+
+/// Pair of -f <list> and the command which follows ':::'.
+type Target = (String, Vec<String>);
+
+/// Split <command> into the first command, pairs of -f <list> and command which follow ':::'
+/// and the files which follow '::::'.
+///   ["cmdA", ":::", "-f", "5", "--", "cmdB", "::::", "a.txt"] => (["cmdA"], [("5", ["cmdB"])], ["a.txt"])
+fn split_targets(args: &[String]) -> Result<(Vec<String>, Vec<Target>, Vec<String>), String> {
+    let (args, files) = match args.iter().position(|a| a == "::::") {
+        Some(i) => (&args[..i], args[i + 1..].to_vec()),
+        None => (args, Vec::new()),
+    };
+    let mut groups = args.split(|a| a == ":::");
+    let first = groups.next().unwrap_or(&[]).to_vec();
+    let mut targets = Vec::new();
+    for group in groups {
+        let (list, rest) = match group {
+            [f, list, rest @ ..] if f == "-f" => (list.to_string(), rest),
+            [f, rest @ ..] if f.starts_with("-f") && f.len() > 2 => (f[2..].to_string(), rest),
+            _ => return Err("-f <list> is required after ':::'".to_string()),
+        };
+        let rest = match rest {
+            [dd, rest @ ..] if dd == "--" => rest,
+            _ => rest,
+        };
+        if rest.is_empty() {
+            return Err(format!("<command> is required after -f {}", list));
         }
+        targets.push((list, rest.to_vec()));
+    }
+    Ok((first, targets, files))
+}
+
+/// Paths listed in the file at `path` line by line (--files). "-" reads the standard input.
+fn read_file_list(path: &str) -> io::Result<Vec<String>> {
+    let list = match path {
+        "-" => io::read_to_string(io::stdin())?,
+        _ => fs::read_to_string(path)?,
+    };
+    Ok(list.lines().filter(|l| !l.is_empty()).map(|l| l.to_string()).collect())
+}
+
+/// Open the file to read instead of the standard input
+fn open_input(path: &str) -> Result<File, String> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+    match file.metadata() {
+        Ok(m) if m.is_dir() => Err(format!("{}: Is a directory", path)),
+        _ => Ok(file),
+    }
+}
+
+/// Temporary file which is removed unless it replaces the original file (-i).
+/// `remove_on_exit` removes it when teip exits with an error, and `drop` does when teip panics.
+struct TempFile(PathBuf);
+
+impl TempFile {
+    fn create(path: PathBuf) -> io::Result<(TempFile, File)> {
+        let file = OpenOptions::new().write(true).create_new(true).open(&path)?;
+        remove_on_exit(Some(path.clone()));
+        Ok((TempFile(path), file))
+    }
+
+    /// Replace the file at `path` with the temporary file
+    fn persist(self, path: &Path) -> io::Result<()> {
+        fs::rename(&self.0, path)?;
+        remove_on_exit(None);
+        std::mem::forget(self);
+        Ok(())
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+        remove_on_exit(None);
+    }
+}
+
+/// Edit the file at `path` in place (-i).
+/// The output is written to a temporary file in the same directory, which replaces the file
+/// only if the targeted commands succeed. The original file is kept as <path><suffix> if `suffix` is given.
+fn edit_in_place<F>(path: &Path, suffix: Option<&str>, tape: &mut Tape, start: F, ignore_child_status: bool)
+where
+    F: Fn(Box<dyn Write + Send>) -> PipeIntercepter,
+{
+    let fail = |e: &dyn std::fmt::Display| -> ! { error_exit(&format!("{}: {}", path.display(), e)) };
+    let file = File::open(path).unwrap_or_else(|e| fail(&e));
+    let metadata = file.metadata().unwrap_or_else(|e| fail(&e));
+    let name = match path.file_name() {
+        Some(name) if metadata.is_file() => name.to_string_lossy(),
+        _ => fail(&"Not a regular file"),
+    };
+    // e.g. "dir/.file.txt.teip1234" for "dir/file.txt"
+    let temp = path.with_file_name(format!(".{}.{}{}", name, env!("CARGO_PKG_NAME"), std::process::id()));
+    let (temp, output) = TempFile::create(temp.clone())
+        .unwrap_or_else(|e| error_exit(&format!("{}: {}", temp.display(), e)));
+
+    let mut ch = start(Box::new(output));
+    tape.feed_file(file, &mut ch)
+        .unwrap_or_else(|e| abort(&mut ch, &format!("{}: {}", path.display(), e), ignore_child_status));
+    let failures = ch.wait();
+    if !failures.is_empty() && !ignore_child_status {
+        child_failure_exit(&failures);
+    }
+
+    fs::set_permissions(&temp.0, metadata.permissions()).unwrap_or_else(|e| fail(&e));
+    if let Some(suffix) = suffix.filter(|s| !s.is_empty()) {
+        let mut backup = OsString::from(path);
+        backup.push(suffix);
+        backup_file(path, Path::new(&backup)).unwrap_or_else(|e| fail(&e));
+    }
+    temp.persist(path).unwrap_or_else(|e| fail(&e));
+}
+
+/// Keep the file at `path` as `backup` before it is replaced (-i[SUFFIX]).
+/// The backup is a hard link to the original file, or a copy if the link cannot be made.
+fn backup_file(path: &Path, backup: &Path) -> io::Result<()> {
+    match fs::remove_file(backup) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    if fs::hard_link(path, backup).is_err() {
+        fs::copy(path, backup)?;
+    }
+    Ok(())
+}
+
+/// Parse the size in bytes with an optional suffix (--queue-size).
+///   "512" => 512, "64K" => 65536, "16M" => 16777216
+fn parse_size(s: &str) -> Result<usize, String> {
+    let (num, unit) = match s.char_indices().last() {
+        Some((i, 'K')) | Some((i, 'k')) => (&s[..i], 1 << 10),
+        Some((i, 'M')) | Some((i, 'm')) => (&s[..i], 1 << 20),
+        Some((i, 'G')) | Some((i, 'g')) => (&s[..i], 1 << 30),
+        _ => (s, 1),
+    };
+    num.parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_mul(unit))
+        .ok_or_else(|| format!("Invalid size '{}'", s))
+}
+
+/// Number of the numbers sorted by --reorder. "all" sorts all of them.
+fn parse_reorder(s: &str) -> Result<usize, String> {
+    match s {
+        "all" => Ok(usize::MAX),
+        _ => s.parse::<usize>().map_err(|_| format!("Invalid size '{}'", s)),
+    }
+}
+
+/// Standard input if it is redirected from a regular file, which -e can read again instead of keeping it.
+#[cfg(unix)]
+fn stdin_file() -> Option<File> {
+    use std::os::unix::io::AsFd;
+    let file = File::from(io::stdin().as_fd().try_clone_to_owned().ok()?);
+    match file.metadata() {
+        Ok(m) if m.is_file() => Some(file),
+        _ => None,
+    }
+}
+
+#[cfg(not(unix))]
+fn stdin_file() -> Option<File> {
+    None
+}
+
+/// Exit with the failures of the targeted commands if they cause the error.
+fn abort(ch: &mut PipeIntercepter, msg: &str, ignore_child_status: bool) -> ! {
+    let failures = ch.wait();
+    if !failures.is_empty() && !ignore_child_status {
+        msg_error(msg);
+        child_failure_exit(&failures);
     }
+    error_exit(msg)
 }
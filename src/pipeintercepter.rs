@@ -1,25 +1,168 @@
-use super::chunk::Chunk;
-use super::spawnutils;
-use super::stringutils::trim_eol;
+use super::diff::Differ;
+use super::report::{JsonReport, Report};
+use super::sink::{HighlightSink, HoleSink, PipeSink, SpawnSink};
 use super::{errors,errors::*};
-use super::{HL,DEFAULT_CAP};
+use teip::{Emitter, Location};
 
-use std::io::{self, BufRead, BufReader, BufWriter, Write};
-use std::sync::mpsc::{self, Sender};
+use std::io::{self, BufWriter, Write};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
 use std::thread::{self, JoinHandle};
 use log::debug;
 
+/// Input stream is devided into multiple Chunks
+pub enum Chunk {
+    Keep(String),                   // a string under masking tape. Printed as is.
+    Hole(usize, String, Location),  // A hole on the masking tape. The string in the hole being processed other thread. The number is the index of the targeted command. The string is kept only for --diff.
+    SHole(usize, String, Location), // Solid hole. A hole and string in this hole. Enabled with -s (solid mode)
+    Flush,                          // Print the chunks so far (the input is idle or --line-buffered)
+    Report(Box<dyn Report + Send>), // Print the following chunks in another form (--diff, --dry-run-format)
+    EOF,                            // End of file
+}
+
+/// Default capacity of the chunks queued for the output thread in bytes (--queue-size)
+pub const DEFAULT_QUEUE_SIZE: usize = 16 * 1024 * 1024;
+
+/// Bytes which a chunk takes besides its string, roughly
+const CHUNK_OVERHEAD: usize = 64;
+
+/// Amount of the chunks queued for the output thread
+///
+/// The reader waits while the queue is full so that the memory usage stays flat
+/// even if the output thread is slower than the reader.
+/// However, it does not wait while the output thread waits for the result of a streaming command,
+/// because the command may print nothing until it receives more holes (e.g. block buffering).
+/// In that case, the pipe to the command blocks the reader when the command is slow.
+struct Backlog {
+    state: Mutex<BacklogState>,
+    cond: Condvar,
+}
+
+struct BacklogState {
+    bytes: usize,  // bytes of the chunks in the queue
+    filling: bool, // the output thread waits for the result of a streaming command
+    closed: bool,  // the output thread exited
+}
+
+impl Backlog {
+    fn new() -> Backlog {
+        Backlog {
+            state: Mutex::new(BacklogState {
+                bytes: 0,
+                filling: false,
+                closed: false,
+            }),
+            cond: Condvar::new(),
+        }
+    }
+
+    fn is_full(&self, cost: usize, capacity: usize) -> bool {
+        let state = self.state.lock().unwrap();
+        state.bytes > 0 && state.bytes + cost > capacity
+    }
+
+    /// Add a chunk to the queue, waiting for the room (reader side)
+    fn push(&self, cost: usize, capacity: usize, streaming: bool) {
+        let mut state = self.state.lock().unwrap();
+        while state.bytes > 0
+            && state.bytes + cost > capacity
+            && !(streaming && state.filling)
+            && !state.closed
+        {
+            state = self.cond.wait(state).unwrap();
+        }
+        state.bytes += cost;
+    }
+
+    /// Remove a chunk from the queue (output thread side)
+    fn pop(&self, cost: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.bytes = state.bytes.saturating_sub(cost);
+        self.cond.notify_all();
+    }
+
+    fn filling(&self, filling: bool) {
+        self.state.lock().unwrap().filling = filling;
+        self.cond.notify_all();
+    }
+
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.cond.notify_all();
+    }
+
+    fn cost(chunk: &Chunk) -> usize {
+        match chunk {
+            Chunk::Keep(msg) | Chunk::Hole(_, msg, _) | Chunk::SHole(_, msg, _) => msg.len() + CHUNK_OVERHEAD,
+            _ => CHUNK_OVERHEAD,
+        }
+    }
+}
+
 /// struct for bypassing input and its interface
+///
+/// Multiple targeted commands can be given (`-f 2 -- cmdA ::: -f 5 -- cmdB`).
+/// Each hole is sent to one of them and the results are printed by the single output thread
+/// to keep the order. The results are given by the HoleSink.
 pub struct PipeIntercepter {
     tx: Sender<Chunk>,
-    pipe_writer: BufWriter<Box<dyn Write + Send + 'static>>, // Not used when -s
-    handler: Option<JoinHandle<()>>,                         // "option dance"
+    pipe_writers: Vec<BufWriter<Box<dyn Write + Send + 'static>>>, // Inputs of the HoleSink. Empty unless it streams
+    handler: Option<JoinHandle<Vec<ChildFailure>>>,          // "option dance"
     line_end: u8,
-    solid: bool,
-    dryrun: bool,
+    line_buffered: bool, // Print the chunks at each line end (--line-buffered)
+    backlog: Arc<Backlog>,
+    queue_size: usize,
+    originals: bool, // The output thread needs the strings in the holes (--diff, --dry-run-format)
 }
 
 impl PipeIntercepter {
+    /// Start the output thread which prints the chunks to `output`, filling the holes by `sink`
+    pub fn start(mut sink: Box<dyn HoleSink>, line_end: u8, output: Box<dyn Write + Send>) -> PipeIntercepter {
+        let (tx, rx) = mpsc::channel();
+        let pipe_writers = sink.inputs().into_iter().map(BufWriter::new).collect();
+        let backlog = Arc::new(Backlog::new());
+        let output_backlog = Arc::clone(&backlog);
+        let handler = thread::spawn(move || PipeIntercepter::output(rx, sink, output, &output_backlog));
+        PipeIntercepter {
+            tx,
+            pipe_writers,
+            handler: Some(handler),
+            line_end,
+            line_buffered: false,
+            backlog,
+            queue_size: DEFAULT_QUEUE_SIZE,
+            originals: false,
+        }
+    }
+
+    /// Capacity of the chunks queued for the output thread in bytes (--queue-size)
+    pub fn queue_size(&mut self, queue_size: usize) {
+        self.queue_size = queue_size;
+    }
+
+    /// Print the chunks at each line end instead of when the buffer is filled (--line-buffered)
+    pub fn line_buffered(&mut self, line_buffered: bool) {
+        self.line_buffered = line_buffered;
+    }
+
+    /// Print the unified diff between the input and the output instead of the output (--diff).
+    /// `path` is the name of the file in the header, or "-" for the standard input.
+    /// Call it again before the next file.
+    pub fn diff(&mut self, path: &str) -> Result<(), errors::ChunkSendError> {
+        self.report(Box::new(Differ::new(path, self.line_end)))
+    }
+
+    /// Print a JSON object for each hole instead of the output (--dry-run-format json).
+    /// `path` is the name of the file, or "-" for the standard input. Call it again before the next file.
+    pub fn json(&mut self, path: &str) -> Result<(), errors::ChunkSendError> {
+        self.report(Box::new(JsonReport::new(path, self.line_end)))
+    }
+
+    fn report(&mut self, report: Box<dyn Report + Send>) -> Result<(), errors::ChunkSendError> {
+        self.originals = true;
+        self.send(Chunk::Report(report))
+    }
+
     /// Spawn an external which receive from bypassed data and modify it
     ///            Example:
     ///            `````````````````````````````````````````````````````````````
@@ -33,7 +176,7 @@ impl PipeIntercepter {
     ///            │ Main thread             │      │ PipeIntercepter                                                            │
     ///            ├─────────────────────────┤      ├────────────────────────────────────────────────────────────────────────────┤
     ///            │  ┌────────────────────┐ │      │                                                    ┌─────────────────────┐ │
-    ///            │  │ line_line_proc     │ │      │                                                    │ start_output thread │ │
+    ///            │  │ Tape::feed         │ │      │                                                    │ start_output thread │ │
     ///            │  ├────────────────────┤ │   ┌──┴────────┐   Keep("AAA")   ┌─────────────────┐       ├─────────────────────┤ │
     ///            │  │                    │ │   │           │   Keep("BBB")   │ tx queue        │       │                     │ │
     ///  [stdin] ──┼──►  "AAA" <= Unmatch──┼─┼───►           │                 │ (std:sync:mpsc) │       │  Keep("AAA")        │ │
@@ -52,65 +195,18 @@ impl PipeIntercepter {
     ///            │                         │      │              "DDD"                                                         │
     ///            └─────────────────────────┘      └────────────────────────────────────────────────────────────────────────────┘
     pub fn start_output(
-        cmds: Vec<String>,
+        cmds: Vec<Vec<String>>,
         line_end: u8,
-        dryrun: bool,
+        dryrun: Option<HighlightSink>,
+        ignore_status: bool,
+        pty: bool,
+        output: Box<dyn Write + Send>,
     ) -> Result<PipeIntercepter, errors::SpawnError> {
-        let (tx, rx) = mpsc::channel();
-        let (child_stdin, child_stdout, _) = spawnutils::exec_cmd(&cmds)?;
-        let pipe_writer = BufWriter::new(child_stdin);
-        let handler = thread::spawn(move || {
-            debug!("thread: spawn");
-            let mut pipe_reader = BufReader::new(child_stdout);
-            let mut result_writer = BufWriter::new(io::stdout());
-            loop {
-                let chunk = match rx.recv() {
-                    Ok(t) => t,
-                    Err(e) => {
-                        msg_error(&e.to_string());
-                        break;
-                    }
-                };
-                match chunk {
-                    Chunk::Keep(msg) => {
-                        debug!("thread: rx.recv <= Keep:[{:?}]", msg);
-                        result_writer
-                            .write(msg.as_bytes())
-                            .unwrap_or_else(|e| exit_silently(&e.to_string()));
-                    }
-                    Chunk::Hole => {
-                        debug!("thread: rx.recv <= Hole");
-                        match PipeIntercepter::read_pipe(&mut pipe_reader, line_end) {
-                            Ok(msg) => {
-                                result_writer
-                                    .write(msg.as_bytes())
-                                    .unwrap_or_else(|e| exit_silently(&e.to_string()));
-                            }
-                            Err(e) => {
-                                // pipe may be exhausted
-                                result_writer.flush().unwrap();
-                                error_exit(&e.to_string())
-                            }
-                        }
-                    }
-                    Chunk::EOF => {
-                        debug!("thread: rx.recv <= EOF");
-                        break;
-                    }
-                    _ => {
-                        error_exit("Exit with bug.");
-                    }
-                };
-            }
-        });
-        Ok(PipeIntercepter {
-            tx,
-            pipe_writer,
-            handler: Some(handler),
-            line_end,
-            solid: false,
-            dryrun,
-        })
+        if let Some(highlight) = dryrun {
+            return Ok(PipeIntercepter::start(Box::new(highlight), line_end, output));
+        }
+        let sink = PipeSink::new(&cmds, line_end, ignore_status, pty)?;
+        Ok(PipeIntercepter::start(Box::new(sink), line_end, output))
     }
 
     /// Spawn an external process for solid mode
@@ -127,7 +223,7 @@ impl PipeIntercepter {
     ///            ├─────────────────────────┤      ├───────────────────────────────────────────────────────────────────────────────────────┤
     ///            │                         │      │                                                    ┌───────────────────────────────┐  │
     ///            │  ┌────────────────────┐ │      │                                                    │ start_solid_output            │  │
-    ///            │  │ line_line_proc     │ │      │                                                    │ thread                        │  │
+    ///            │  │ Tape::feed         │ │      │                                                    │ thread                        │  │
     ///            │  ├────────────────────┤ │   ┌──┴────────┐   Keep("AAA")   ┌─────────────────┐       ├───────────────────────────────┤  │
     ///            │  │                    │ │   │           │   Keep("BBB")   │ tx queue        │       │                               │  │
     ///  [stdin] ──┼──►  "AAA" <= Unmatch──┼─┼───►           │                 │ (std:sync:mpsc) │       │ Keep("AAA")                   │  │
@@ -150,149 +246,238 @@ impl PipeIntercepter {
     ///                                             │                                                    └───────────────────────────────┘  │
     ///                                             └───────────────────────────────────────────────────────────────────────────────────────┘
     pub fn start_solid_output(
-        cmds: Vec<String>,
+        cmds: Vec<Vec<String>>,
         line_end: u8,
-        dryrun: bool,
+        dryrun: Option<HighlightSink>,
         chomp: bool,
         replace_str: Option<String>,
+        shell: Option<String>,
+        output: Box<dyn Write + Send>,
     ) -> Result<PipeIntercepter, errors::SpawnError> {
-        let (tx, rx) = mpsc::channel();
-        let is_replace = replace_str.is_some();
-        let replace_str = replace_str.unwrap_or_else(|| "".to_string());
-        let handler = thread::spawn(move || {
-            debug!("thread: spawn");
-            let mut writer = BufWriter::new(io::stdout());
-            loop {
-                let chunk = match rx.recv() {
-                    Ok(t) => t,
-                    Err(e) => {
-                        msg_error(&e.to_string());
-                        break;
-                    }
-                };
-                match chunk {
-                    Chunk::Keep(msg) => {
-                        debug!("thread: rx.recv <= Keep:[{:?}]", msg);
-                        writer
-                            .write(msg.as_bytes())
-                            .unwrap_or_else(|e| exit_silently(&e.to_string()));
-                    }
-                    Chunk::SHole(msg) => {
-                        debug!("thread: rx.recv <= SHole:[{:?}]", msg);
-                        // -I option
-                        if is_replace {
-                            let result = spawnutils::exec_cmd_sync_replace(msg, &cmds, line_end, chomp, replace_str.as_ref());
-                            writer
-                                .write(result.as_bytes())
-                                .unwrap_or_else(|e| exit_silently(&e.to_string()));
-                        } else {
-                            let result = spawnutils::exec_cmd_sync(msg, &cmds, line_end, chomp);
-                            writer
-                                .write(result.as_bytes())
-                                .unwrap_or_else(|e| exit_silently(&e.to_string()));
-                        }
+        if let Some(highlight) = dryrun {
+            return Ok(PipeIntercepter::start(Box::new(highlight), line_end, output));
+        }
+        let sink = SpawnSink::new(cmds, line_end, chomp, replace_str, shell);
+        Ok(PipeIntercepter::start(Box::new(sink), line_end, output))
+    }
+
+    /// Output thread. Print the chunks in order until EOF, filling the holes by `sink`.
+    fn output(
+        rx: Receiver<Chunk>,
+        mut sink: Box<dyn HoleSink>,
+        output: Box<dyn Write + Send>,
+        backlog: &Backlog,
+    ) -> Vec<ChildFailure> {
+        debug!("thread: spawn");
+        let mut writer = BufWriter::new(output);
+        let mut report: Option<Box<dyn Report + Send>> = None;
+        let mut gathered = Vec::new();
+        if sink.gathers() {
+            // Keep all of the chunks until the end of the input (--collect)
+            while let Some(chunk) = PipeIntercepter::recv(&rx, backlog) {
+                gathered.push(chunk);
+            }
+            let holes = gathered
+                .iter()
+                .filter_map(|c| match c {
+                    Chunk::SHole(target, msg, _) => Some((*target, msg.as_str())),
+                    _ => None,
+                })
+                .collect();
+            sink.prepare(holes)
+                .unwrap_or_else(|e| PipeIntercepter::fail(&mut writer, e, None));
+        }
+        let mut gathered = gathered.into_iter();
+        while let Some(chunk) = gathered.next().or_else(|| PipeIntercepter::recv(&rx, backlog)) {
+            let (target, msg, original, loc) = match chunk {
+                Chunk::Keep(msg) => {
+                    match report {
+                        Some(ref mut r) => r.keep(&msg, &mut writer),
+                        None => writer.write_all(msg.as_bytes()),
                     }
-                    Chunk::EOF => {
-                        debug!("thread: rx.recv <= EOF");
-                        break;
+                    .unwrap_or_else(|e| PipeIntercepter::broken(e));
+                    continue;
+                }
+                Chunk::Flush => {
+                    writer.flush().unwrap_or_else(|e| PipeIntercepter::broken(e));
+                    continue;
+                }
+                Chunk::Report(next) => {
+                    if let Some(mut r) = report.replace(next) {
+                        r.finish(&mut writer).unwrap_or_else(|e| PipeIntercepter::broken(e));
                     }
-                    _ => {
-                        error_exit("Exit with bug.");
+                    continue;
+                }
+                Chunk::Hole(target, original, loc) => {
+                    // The command may need more holes to print the result
+                    backlog.filling(true);
+                    (target, String::new(), original, loc)
+                }
+                Chunk::SHole(target, msg, loc) => {
+                    let original = if report.is_some() { msg.clone() } else { String::new() };
+                    (target, msg, original, loc)
+                }
+                Chunk::EOF => break,
+            };
+            let result = sink
+                .fill(target, msg, &loc)
+                .unwrap_or_else(|e| PipeIntercepter::fail(&mut writer, e, Some(loc.seq)));
+            backlog.filling(false);
+            match report {
+                Some(ref mut r) => r.hole(&original, &result, &loc, &mut writer),
+                None => writer.write_all(result.as_bytes()),
+            }
+            .unwrap_or_else(|e| PipeIntercepter::broken(e));
+        }
+        if let Some(mut r) = report.take() {
+            r.finish(&mut writer).unwrap_or_else(|e| PipeIntercepter::broken(e));
+        }
+        writer.flush().unwrap_or_else(|e| PipeIntercepter::broken(e));
+        drop(writer);
+        backlog.close();
+        sink.finish()
+    }
+
+    /// Receive a chunk in the output thread. Returns None at EOF.
+    fn recv(rx: &Receiver<Chunk>, backlog: &Backlog) -> Option<Chunk> {
+        let chunk = rx.recv();
+        if let Ok(ref c) = chunk {
+            backlog.pop(Backlog::cost(c));
+        }
+        match chunk {
+            Ok(Chunk::EOF) => {
+                debug!("thread: rx.recv <= EOF");
+                None
+            }
+            Ok(chunk) => {
+                match chunk {
+                    Chunk::Keep(ref msg) => debug!("thread: rx.recv <= Keep:[{:?}]", msg),
+                    Chunk::Hole(target, _, ref loc) => debug!("thread: rx.recv <= Hole({}) at {:?}", target, loc),
+                    Chunk::SHole(target, ref msg, ref loc) => {
+                        debug!("thread: rx.recv <= SHole({}):[{:?}] at {:?}", target, msg, loc)
                     }
-                };
+                    Chunk::Flush => debug!("thread: rx.recv <= Flush"),
+                    Chunk::Report(_) => debug!("thread: rx.recv <= Report"),
+                    Chunk::EOF => {}
+                }
+                Some(chunk)
             }
-        });
-        let dummy = Box::new(io::sink());
-        Ok(PipeIntercepter {
-            tx,
-            pipe_writer: BufWriter::new(dummy),
-            handler: Some(handler),
-            line_end,
-            solid: true,
-            dryrun,
-        })
+            Err(e) => {
+                msg_error(&e.to_string());
+                None
+            }
+        }
     }
 
-    fn read_pipe<R: BufRead + ?Sized>(
-        reader: &mut R,
-        line_end: u8,
-    ) -> Result<String, errors::PipeReceiveError> {
-        debug!("thread: read_pipe");
-        let mut buf = Vec::with_capacity(DEFAULT_CAP);
-        let n = reader
-            .read_until(line_end, &mut buf)
-            .map_err(|e| errors::PipeReceiveError::Io(e))?;
-        if n == 0 {
-            // If pipe is exhausted, throw error.
-            return Err(errors::PipeReceiveError::EndOfFd);
+    /// Exit because the output cannot be written.
+    /// The closed pipe is not reported since the reader may have stopped intentionally (e.g. head).
+    fn broken(e: io::Error) -> ! {
+        match e.kind() {
+            io::ErrorKind::BrokenPipe => exit_silently(&e.to_string()),
+            _ => error_exit(&e.to_string()),
+        }
+    }
+
+    /// Print the output so far and exit with the error of the sink
+    fn fail(writer: &mut BufWriter<Box<dyn Write + Send>>, e: SinkError, seq: Option<u64>) -> ! {
+        writer.flush().unwrap_or_else(|e| PipeIntercepter::broken(e));
+        match (e, seq) {
+            (SinkError::Hole(e), Some(seq)) => error_exit(&format!("{} (hole #{})", e, seq)),
+            (SinkError::Hole(e), None) => error_exit(&e),
+            (SinkError::Command(e, Some(f)), _) => {
+                msg_error(&e);
+                child_failure_exit(&[f])
+            }
+            (SinkError::Command(e, None), _) => error_exit(&e),
+        }
+    }
+
+    /// Queue the chunk for the output thread. Wait while the queue is full (--queue-size).
+    fn send(&mut self, chunk: Chunk) -> Result<(), errors::ChunkSendError> {
+        let cost = Backlog::cost(&chunk);
+        if self.backlog.is_full(cost, self.queue_size) {
+            // The output thread may be waiting for the results of the holes left in the buffers.
+            for pipe_writer in self.pipe_writers.iter_mut() {
+                pipe_writer.flush().map_err(errors::ChunkSendError::Pipe)?;
+            }
         }
-        // Remove line_end from buf.
-        trim_eol(&mut buf);
-        Ok(String::from_utf8_lossy(&buf).to_string())
+        self.backlog.push(cost, self.queue_size, !self.pipe_writers.is_empty());
+        self.tx.send(chunk).map_err(|_| errors::ChunkSendError::Channel)
     }
 
+    /// Close the pipe and wait for all of the output to be printed.
+    /// Returns the targeted commands which did not finish successfully.
+    pub fn wait(&mut self) -> Vec<ChildFailure> {
+        let handler = match self.handler.take() {
+            Some(h) => h,
+            None => return Vec::new(),
+        };
+        // The output thread may still be waiting for chunks if the input was not read to the end.
+        let _ = self.tx.send(Chunk::EOF);
+        debug!("close pipe");
+        // Drop the writers to close the pipes.
+        self.pipe_writers.clear();
+        handler.join().unwrap()
+    }
+}
+
+impl Emitter for PipeIntercepter {
     /// Print string as is, that means it outputs to stdout without any modifications.
     /// This is data "under the masking tape".
-    pub fn send_keep(&self, msg: String) -> Result<(), errors::ChunkSendError> {
+    fn send_keep(&mut self, msg: String) -> Result<(), errors::ChunkSendError> {
         debug!("tx.send => Channle({:?})", msg);
-        self.tx
-            .send(Chunk::Keep(msg))
-            .map_err(|e| errors::ChunkSendError::Channel(e))?;
+        let line_end = msg.as_bytes().contains(&self.line_end);
+        self.send(Chunk::Keep(msg))?;
+        if self.line_buffered && line_end {
+            self.send_flush()?;
+        }
         Ok(())
     }
 
-    /// Bypassing strings to the pipe and will be modified by the targeted command.
+    /// Bypassing strings to the pipe and will be modified by the `target`-th targeted command.
     /// This is data is in the hole on the masking tape".
-    pub fn send_byps(&mut self, msg: String) -> Result<(), errors::ChunkSendError> {
-        if self.dryrun {
-            // Highlight the string instead of bypassing
-            let msg_highlighted: String;
-            msg_highlighted = HL[0].to_string() + &msg + HL[1];
-            debug!("tx.send => Channle({:?})", msg_highlighted);
-            self.tx
-                .send(Chunk::Keep(msg_highlighted))
-                .map_err(|e| errors::ChunkSendError::Channel(e))?;
+    fn send_byps_to(&mut self, target: usize, msg: String, loc: Location) -> Result<(), errors::ChunkSendError> {
+        if target >= self.pipe_writers.len() {
+            debug!("tx.send => Solid({:?})", msg);
+            self.send(Chunk::SHole(target, msg, loc))?;
             return Ok(());
         }
-        if self.solid {
-            debug!("tx.send => Solid({:?})", msg);
-            self.tx
-                .send(Chunk::SHole(msg))
-                .map_err(|e| errors::ChunkSendError::Channel(e))?;
-            Ok(())
-        } else {
-            debug!("tx.send => Hole");
-            self.tx
-                .send(Chunk::Hole)
-                .map_err(|e| errors::ChunkSendError::Channel(e))?;
-            debug!("stdin => {}[line_end]", msg);
-            // FIXME: Marging line_end to the end of the string may improve the performance.
-            //        Need benchmarking.
-            self.pipe_writer
-                .write(msg.as_bytes())
-                .map_err(|e| errors::ChunkSendError::Pipe(e))?;
-            self.pipe_writer
-                .write(&[self.line_end])
-                .map_err(|e| errors::ChunkSendError::Pipe(e))?;
-            Ok(())
+        debug!("tx.send => Hole");
+        let original = if self.originals { msg.clone() } else { String::new() };
+        self.send(Chunk::Hole(target, original, loc))?;
+        debug!("stdin => {}[line_end]", msg);
+        let pipe_writer = &mut self.pipe_writers[target];
+        // FIXME: Marging line_end to the end of the string may improve the performance.
+        //        Need benchmarking.
+        pipe_writer
+            .write(msg.as_bytes())
+            .map_err(|e| errors::ChunkSendError::Pipe(e))?;
+        pipe_writer
+            .write(&[self.line_end])
+            .map_err(|e| errors::ChunkSendError::Pipe(e))?;
+        Ok(())
+    }
+
+    /// Send the holes so far to the targeted commands and let the output thread print the chunks so far
+    fn send_flush(&mut self) -> Result<(), errors::ChunkSendError> {
+        debug!("tx.send => Flush");
+        for pipe_writer in self.pipe_writers.iter_mut() {
+            pipe_writer.flush().map_err(errors::ChunkSendError::Pipe)?;
         }
+        self.send(Chunk::Flush)?;
+        Ok(())
     }
 
     /// Notify PipeIntercepter the end of file to exit process
-    pub fn send_eof(&self) -> Result<(), errors::ChunkSendError> {
+    fn send_eof(&mut self) -> Result<(), errors::ChunkSendError> {
         debug!("tx.send => EOF");
-        self.tx
-            .send(Chunk::EOF)
-            .map_err(|e| errors::ChunkSendError::Channel(e))?;
+        self.send(Chunk::EOF)?;
         Ok(())
     }
 }
 
 impl Drop for PipeIntercepter {
     fn drop(&mut self) {
-        debug!("close pipe");
-        // Replace the writer with a dummy object to close the pipe.
-        self.pipe_writer = BufWriter::new(Box::new(io::sink()));
-        self.handler.take().unwrap().join().unwrap();
+        self.wait();
     }
 }
@@ -0,0 +1,479 @@
+// Predicate over the fields of a line (--where), which looks like a pattern of awk.
+//   $3 > 500 && $7 ~ /POST/
+//   NR % 2 == 0 || $NF == "end"
+use regex::Regex;
+use std::borrow::Cow;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Num(f64),
+    Str(String),
+    Regex(String),
+    Ident(String),
+    Op(&'static str),
+}
+
+// Longer operators must come first
+const OPS: [&str; 19] = [
+    "&&", "||", "==", "!=", "<=", ">=", "!~", "<", ">", "~", "!", "+", "-", "*", "/", "%", "(", ")", "$",
+];
+
+#[derive(Clone, Debug)]
+enum Node {
+    Num(f64),
+    Str(String),
+    Field(Box<Node>), // $N
+    Nr,               // Number of the record
+    Nf,               // Number of the fields
+    Match(Box<Node>, Regex, bool), // left ~ /re/, left !~ /re/ (negated)
+    Not(Box<Node>),
+    Neg(Box<Node>),
+    Binary(&'static str, Box<Node>, Box<Node>),
+}
+
+/// Value during the evaluation, which follows the conversion rules of awk.
+#[derive(Clone, Debug)]
+enum Value<'a> {
+    Num(f64),
+    Str(Cow<'a, str>),
+    StrNum(Cow<'a, str>, f64), // A field which looks like a number
+}
+
+impl Value<'_> {
+    /// A field is compared as a number if it looks like a number
+    fn from_field(s: Cow<'_, str>) -> Value<'_> {
+        match looks_numeric(&s) {
+            Some(n) => Value::StrNum(s, n),
+            None => Value::Str(s),
+        }
+    }
+
+    fn num(&self) -> f64 {
+        match self {
+            Value::Num(n) | Value::StrNum(_, n) => *n,
+            Value::Str(s) => numeric_prefix(s),
+        }
+    }
+
+    fn str(&self) -> Cow<'_, str> {
+        match self {
+            Value::Num(n) => Cow::Owned(num_to_string(*n)),
+            Value::Str(s) | Value::StrNum(s, _) => Cow::Borrowed(s),
+        }
+    }
+
+    fn truth(&self) -> bool {
+        match self {
+            Value::Num(n) | Value::StrNum(_, n) => *n != 0.0,
+            Value::Str(s) => !s.is_empty(),
+        }
+    }
+
+    fn is_num(&self) -> bool {
+        !matches!(self, Value::Str(_))
+    }
+}
+
+fn bool_value(b: bool) -> Value<'static> {
+    Value::Num(if b { 1.0 } else { 0.0 })
+}
+
+/// Number if the whole string is a decimal number with optional blanks around it
+fn looks_numeric(s: &str) -> Option<f64> {
+    let t = s.trim();
+    if t.is_empty() || !t.bytes().any(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    if !t.bytes().all(|b| b.is_ascii_digit() || matches!(b, b'+' | b'-' | b'.' | b'e' | b'E')) {
+        return None;
+    }
+    t.parse().ok()
+}
+
+/// Leading number of the string, or 0 ("12abc" => 12)
+fn numeric_prefix(s: &str) -> f64 {
+    let t = s.trim_start();
+    (1..=t.len())
+        .rev()
+        .filter(|&i| t.is_char_boundary(i))
+        .find_map(|i| looks_numeric(&t[..i]))
+        .unwrap_or(0.0)
+}
+
+fn num_to_string(n: f64) -> String {
+    if n.fract() == 0.0 && n.abs() < 1e16 {
+        format!("{}", n as i64)
+    } else {
+        format!("{}", n)
+    }
+}
+
+/// Boolean expression over the fields of a line
+#[derive(Clone, Debug)]
+pub struct Expr {
+    root: Node,
+}
+
+impl Expr {
+    /// Parse the expression. `regex` compiles the regular expressions in `/re/` and the right side of `~`.
+    pub fn parse<F>(src: &str, regex: F) -> Result<Expr, String>
+    where
+        F: Fn(&str) -> Result<Regex, String>,
+    {
+        let err = |msg: String| format!("Invalid expression '{}': {}", src, msg);
+        let tokens = tokenize(src).map_err(err)?;
+        let mut p = Parser {
+            tokens,
+            pos: 0,
+            regex: &regex,
+        };
+        let root = p.or().map_err(err)?;
+        if let Some(t) = p.peek() {
+            return Err(err(format!("unexpected {}", describe(t))));
+        }
+        Ok(Expr { root })
+    }
+
+    /// Whether the `nr`-th record `line`, which is split into `fields`, satisfies the expression.
+    pub fn matches(&self, line: &str, fields: &[Cow<str>], nr: u64) -> bool {
+        eval(&self.root, line, fields, nr).truth()
+    }
+}
+
+fn eval<'a>(node: &Node, line: &'a str, fields: &'a [Cow<str>], nr: u64) -> Value<'a> {
+    match node {
+        Node::Num(n) => Value::Num(*n),
+        Node::Str(s) => Value::Str(Cow::Owned(s.clone())),
+        Node::Nr => Value::Num(nr as f64),
+        Node::Nf => Value::Num(fields.len() as f64),
+        Node::Field(i) => {
+            let i = eval(i, line, fields, nr).num();
+            if i < 0.5 && i > -1.0 {
+                Value::from_field(Cow::Borrowed(line))
+            } else if i >= 1.0 && (i as usize) <= fields.len() {
+                Value::from_field(Cow::Borrowed(fields[i as usize - 1].as_ref()))
+            } else {
+                // Uninitialized value is both "" and 0
+                Value::StrNum(Cow::Borrowed(""), 0.0)
+            }
+        }
+        Node::Match(left, re, negated) => {
+            let left = eval(left, line, fields, nr);
+            bool_value(re.is_match(&left.str()) != *negated)
+        }
+        Node::Not(e) => bool_value(!eval(e, line, fields, nr).truth()),
+        Node::Neg(e) => Value::Num(-eval(e, line, fields, nr).num()),
+        Node::Binary(op, l, r) => {
+            let l = eval(l, line, fields, nr);
+            // && and || do not evaluate the right side if not needed
+            match *op {
+                "&&" => return bool_value(l.truth() && eval(r, line, fields, nr).truth()),
+                "||" => return bool_value(l.truth() || eval(r, line, fields, nr).truth()),
+                _ => {}
+            }
+            let r = eval(r, line, fields, nr);
+            match *op {
+                "+" => Value::Num(l.num() + r.num()),
+                "-" => Value::Num(l.num() - r.num()),
+                "*" => Value::Num(l.num() * r.num()),
+                "/" => Value::Num(l.num() / r.num()),
+                "%" => Value::Num(l.num() % r.num()),
+                _ => {
+                    // Compared as numbers only if both sides are numbers
+                    let ord = if l.is_num() && r.is_num() {
+                        l.num().partial_cmp(&r.num())
+                    } else {
+                        Some(l.str().cmp(&r.str()))
+                    };
+                    bool_value(match (*op, ord) {
+                        ("==", Some(o)) => o.is_eq(),
+                        ("!=", Some(o)) => o.is_ne(),
+                        ("<", Some(o)) => o.is_lt(),
+                        ("<=", Some(o)) => o.is_le(),
+                        (">", Some(o)) => o.is_gt(),
+                        (">=", Some(o)) => o.is_ge(),
+                        ("!=", None) => true,
+                        _ => false,
+                    })
+                }
+            }
+        }
+    }
+}
+
+fn describe(t: &Token) -> String {
+    match t {
+        Token::Num(n) => num_to_string(*n),
+        Token::Str(s) => format!("\"{}\"", s),
+        Token::Regex(s) => format!("/{}/", s),
+        Token::Ident(s) => s.to_string(),
+        Token::Op(s) => format!("`{}'", s),
+    }
+}
+
+fn tokenize(src: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = src.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        // `/` is a division after an operand, otherwise it starts a regular expression
+        let after_operand = match tokens.last() {
+            Some(Token::Op(op)) => *op == ")",
+            Some(_) => true,
+            None => false,
+        };
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() || (c == '.' && chars.get(i + 1).is_some_and(|c| c.is_ascii_digit())) {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            if i < chars.len() && (chars[i] == 'e' || chars[i] == 'E') {
+                let mut j = i + 1;
+                if j < chars.len() && (chars[j] == '+' || chars[j] == '-') {
+                    j += 1;
+                }
+                if j < chars.len() && chars[j].is_ascii_digit() {
+                    i = j;
+                    while i < chars.len() && chars[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                }
+            }
+            let s: String = chars[start..i].iter().collect();
+            tokens.push(Token::Num(s.parse().map_err(|_| format!("invalid number {}", s))?));
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else if c == '"' || (c == '/' && !after_operand) {
+            let (s, end) = quoted(&chars, i)?;
+            tokens.push(if c == '"' { Token::Str(s) } else { Token::Regex(s) });
+            i = end + 1;
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+            let op = OPS
+                .iter()
+                .find(|op| rest.starts_with(**op))
+                .ok_or_else(|| format!("unexpected `{}'", c))?;
+            tokens.push(Token::Op(op));
+            i += op.len();
+        }
+    }
+    Ok(tokens)
+}
+
+/// Contents of "string" or /regex/ which starts at `chars[start]`, and where it ends.
+fn quoted(chars: &[char], start: usize) -> Result<(String, usize), String> {
+    let delim = chars[start];
+    let mut s = String::new();
+    let mut i = start + 1;
+    while i < chars.len() {
+        match chars[i] {
+            c if c == delim => return Ok((s, i)),
+            '\\' if i + 1 < chars.len() => {
+                i += 1;
+                match (delim, chars[i]) {
+                    ('"', 'n') => s.push('\n'),
+                    ('"', 't') => s.push('\t'),
+                    ('"', c) => s.push(c),
+                    // Escapes in /re/ are interpreted by the regex, except for the delimiter
+                    (_, '/') => s.push('/'),
+                    (_, c) => {
+                        s.push('\\');
+                        s.push(c);
+                    }
+                }
+            }
+            c => s.push(c),
+        }
+        i += 1;
+    }
+    Err(format!("unterminated {}", if delim == '"' { "string" } else { "regex" }))
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    regex: &'a dyn Fn(&str) -> Result<Regex, String>,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn eat(&mut self, ops: &[&'static str]) -> Option<&'static str> {
+        match self.peek() {
+            Some(Token::Op(op)) if ops.contains(op) => {
+                let op = *op;
+                self.pos += 1;
+                Some(op)
+            }
+            _ => None,
+        }
+    }
+
+    fn or(&mut self) -> Result<Node, String> {
+        let mut left = self.and()?;
+        while let Some(op) = self.eat(&["||"]) {
+            left = Node::Binary(op, Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Node, String> {
+        let mut left = self.not()?;
+        while let Some(op) = self.eat(&["&&"]) {
+            left = Node::Binary(op, Box::new(left), Box::new(self.not()?));
+        }
+        Ok(left)
+    }
+
+    fn not(&mut self) -> Result<Node, String> {
+        if self.eat(&["!"]).is_some() {
+            return Ok(Node::Not(Box::new(self.not()?)));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Node, String> {
+        let left = self.additive()?;
+        if let Some(op) = self.eat(&["==", "!=", "<", "<=", ">", ">="]) {
+            return Ok(Node::Binary(op, Box::new(left), Box::new(self.additive()?)));
+        }
+        if let Some(op) = self.eat(&["~", "!~"]) {
+            let re = match self.peek() {
+                Some(Token::Regex(s)) | Some(Token::Str(s)) => (self.regex)(s)?,
+                _ => return Err(format!("/regex/ or \"regex\" is expected after `{}'", op)),
+            };
+            self.pos += 1;
+            return Ok(Node::Match(Box::new(left), re, op == "!~"));
+        }
+        Ok(left)
+    }
+
+    fn additive(&mut self) -> Result<Node, String> {
+        let mut left = self.multiplicative()?;
+        while let Some(op) = self.eat(&["+", "-"]) {
+            left = Node::Binary(op, Box::new(left), Box::new(self.multiplicative()?));
+        }
+        Ok(left)
+    }
+
+    fn multiplicative(&mut self) -> Result<Node, String> {
+        let mut left = self.unary()?;
+        while let Some(op) = self.eat(&["*", "/", "%"]) {
+            left = Node::Binary(op, Box::new(left), Box::new(self.unary()?));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        match self.eat(&["-", "+", "!"]) {
+            Some("-") => Ok(Node::Neg(Box::new(self.unary()?))),
+            Some("!") => Ok(Node::Not(Box::new(self.unary()?))),
+            Some(_) => self.unary(),
+            None => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Node, String> {
+        let token = self.peek().cloned().ok_or("unexpected end of the expression")?;
+        self.pos += 1;
+        match token {
+            Token::Num(n) => Ok(Node::Num(n)),
+            Token::Str(s) => Ok(Node::Str(s)),
+            // /re/ alone is the same as $0 ~ /re/
+            Token::Regex(s) => Ok(Node::Match(Box::new(Node::Field(Box::new(Node::Num(0.0)))), (self.regex)(&s)?, false)),
+            Token::Ident(s) if s == "NR" => Ok(Node::Nr),
+            Token::Ident(s) if s == "NF" => Ok(Node::Nf),
+            Token::Ident(s) => Err(format!("unknown variable {}", s)),
+            Token::Op("$") => Ok(Node::Field(Box::new(self.primary()?))),
+            Token::Op("(") => {
+                let node = self.or()?;
+                self.eat(&[")"]).ok_or("missing `)'")?;
+                Ok(node)
+            }
+            t => Err(format!("unexpected {}", describe(&t))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn matches(src: &str, line: &str) -> bool {
+        let expr = Expr::parse(src, |p| Regex::new(p).map_err(|e| e.to_string())).unwrap();
+        let fields: Vec<Cow<str>> = line.split_whitespace().map(Cow::Borrowed).collect();
+        expr.matches(line, &fields, 7)
+    }
+
+    #[test]
+    fn test_compare() {
+        assert!(matches("$3 > 500", "a b 1000"));
+        // Numbers are not compared as strings
+        assert!(!matches("$3 > 500", "a b 99"));
+        assert!(matches("$3 > \"500\"", "a b 99"));
+        assert!(matches("$1 == \"a\" && $2 != \"a\"", "a b"));
+        assert!(matches("$1 < $2", "10 9x"));
+        assert!(matches("$2 >= 1.5e1", "x 15.0"));
+        assert!(matches("$5 == 0 && $5 == \"\"", "a b"));
+        assert!(!matches("$1", "0"));
+        assert!(matches("$1", "0x"));
+    }
+
+    #[test]
+    fn test_match() {
+        assert!(matches("$2 ~ /^PO/", "GET POST"));
+        assert!(matches("$2 !~ \"GET\"", "GET POST"));
+        assert!(matches("/T P/", "GET POST"));
+        assert!(!matches("!/T P/", "GET POST"));
+        assert!(matches("$1 ~ /a\\/b/", "a/b"));
+    }
+
+    #[test]
+    fn test_variables() {
+        assert!(matches("NF == 3 && $NF == \"c\"", "a b c"));
+        assert!(matches("$(NF-1) == \"b\"", "a b c"));
+        assert!(matches("NR % 2 == 1", "a"));
+        assert!(matches("NR / 7 == 1 && -NR == 0 - 7", "a"));
+        assert!(matches("$0 == \"a  b\"", "a  b"));
+    }
+
+    #[test]
+    fn test_precedence() {
+        assert!(matches("1 || 0 && 0", ""));
+        assert!(!matches("(1 || 0) && 0", ""));
+        assert!(matches("!0 && 1 + 2 * 3 == 7", ""));
+    }
+
+    #[test]
+    fn test_parse_error() {
+        let regex = |p: &str| Regex::new(p).map_err(|e| e.to_string());
+        assert!(Expr::parse("$1 >", regex).is_err());
+        assert!(Expr::parse("$1 > 3)", regex).is_err());
+        assert!(Expr::parse("(NR", regex).is_err());
+        assert!(Expr::parse("FOO == 1", regex).is_err());
+        assert!(Expr::parse("$1 ~ $2", regex).is_err());
+        assert!(Expr::parse("\"abc", regex).is_err());
+        assert!(Expr::parse("$1 ~ /(/", regex).is_err());
+        assert!(Expr::parse("$1 = 1", regex).is_err());
+    }
+
+    #[test]
+    fn test_numeric() {
+        assert_eq!(looks_numeric(" 12 "), Some(12.0));
+        assert_eq!(looks_numeric("-1.5e3"), Some(-1500.0));
+        assert_eq!(looks_numeric("inf"), None);
+        assert_eq!(looks_numeric("1a"), None);
+        assert_eq!(numeric_prefix("12abc"), 12.0);
+        assert_eq!(num_to_string(3.0), "3");
+        assert_eq!(num_to_string(0.5), "0.5");
+    }
+}
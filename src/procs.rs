@@ -1,253 +1,386 @@
-use super::pipeintercepter::PipeIntercepter;
-use super::spawnutils;
-use super::list;
-use super::{errors,errors::*};
-use super::stringutils;
+use super::chunk::Index;
+use super::csv::parser::{NfaState, Parser};
+use super::predicate::expr::Expr;
+use super::sed::address::Address;
+use super::selector::{Selector, Span};
+use super::exoffload;
+use super::stringutils::{self, Region};
+use super::{list, CMD};
 use regex::Regex;
-use super::DEFAULT_CAP;
-use std::io::{self, BufRead};
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Read};
+use std::sync::mpsc::Receiver;
+
+/// Whole record is a hole if `selected` is true
+fn line_spans(record: &str, selected: bool) -> Vec<Span> {
+    if selected {
+        vec![Span::new(0, record.len(), Index::None)]
+    } else {
+        vec![]
+    }
+}
+
+/// Holes made from the matched parts `(start, end)` of the record ( -o -g )
+/// If `invert` is true, the unmatched parts are the holes instead.
+pub fn match_spans(matches: impl Iterator<Item = (usize, usize)>, len: usize, invert: bool) -> Vec<Span> {
+    let mut spans = Vec::new();
+    let mut left_index = 0;
+    for (i, (start, end)) in matches.enumerate() {
+        // Ignore empty string.
+        // Regex "*" matches empty, but , in most situations,
+        // handling empty string is not helpful for users.
+        if invert && left_index < start {
+            spans.push(Span::new(left_index, start, Index::None));
+        }
+        if !invert {
+            spans.push(Span::new(start, end, Index::Match(i + 1)));
+        }
+        left_index = end;
+    }
+    if invert && left_index < len {
+        spans.push(Span::new(left_index, len, Index::None));
+    }
+    spans
+}
 
 /// Bypassing particular lines based on given list ( -l )
-pub fn line_line_proc(
-    ch: &mut PipeIntercepter,
-    ranges: &Vec<list::ranges::Range>,
-    line_end: u8,
-) -> Result<(), errors::ChunkSendError> {
-    let mut i: usize = 0;
-    let mut ri: usize = 0;
-    let stdin = io::stdin();
-    loop {
-        let mut buf = Vec::with_capacity(DEFAULT_CAP);
-        match stdin.lock().read_until(line_end, &mut buf) {
-            Ok(n) => {
-                let eol = stringutils::trim_eol(&mut buf);
-                let line = String::from_utf8_lossy(&buf).to_string();
-                if n == 0 {
-                    ch.send_eof()?;
-                    break;
-                }
-                if ranges[ri].high < (i + 1) && (ri + 1) < ranges.len() {
-                    ri += 1;
-                }
-                if ranges[ri].low <= (i + 1) && (i + 1) <= ranges[ri].high {
-                    ch.send_byps(line.to_string())?;
-                } else {
-                    ch.send_keep(line.to_string())?;
-                }
-                ch.send_keep(eol)?;
-            }
-            Err(e) => msg_error(&e.to_string()),
+pub struct LineSelector {
+    ranges: Vec<list::ranges::Range>,
+    ri: usize,
+    nr: usize, // number of read
+}
+
+impl LineSelector {
+    pub fn new(ranges: Vec<list::ranges::Range>) -> LineSelector {
+        LineSelector { ranges, ri: 0, nr: 0 }
+    }
+}
+
+impl Selector for LineSelector {
+    fn open(&mut self, input: Box<dyn Read + Send>, _line_end: u8) -> io::Result<Box<dyn Read + Send>> {
+        self.ri = 0;
+        self.nr = 0;
+        Ok(input)
+    }
+
+    fn select(&mut self, record: &str) -> Vec<Span> {
+        self.nr += 1;
+        let ranges = &self.ranges;
+        if ranges[self.ri].high < self.nr && (self.ri + 1) < ranges.len() {
+            self.ri += 1;
         }
-        i += 1;
+        line_spans(record, ranges[self.ri].low <= self.nr && self.nr <= ranges[self.ri].high)
     }
-    Ok(())
 }
 
 /// Bypassing particular lines based on Regular Expression ( -g )
-pub fn regex_line_proc(
-    ch: &mut PipeIntercepter,
-    re: &Regex,
+pub struct RegexLineSelector {
+    re: Regex,
     invert: bool,
-    line_end: u8,
-) -> Result<(), errors::ChunkSendError> {
-    let stdin = io::stdin();
-    loop {
-        let mut buf = Vec::with_capacity(DEFAULT_CAP);
-        match stdin.lock().read_until(line_end, &mut buf) {
-            Ok(n) => {
-                let eol = stringutils::trim_eol(&mut buf);
-                if n == 0 {
-                    ch.send_eof()?;
-                    break;
-                }
-                let line = String::from_utf8_lossy(&buf).to_string();
-                if re.is_match(&line) {
-                    if invert {
-                        ch.send_keep(line.to_string())?;
-                    } else {
-                        ch.send_byps(line.to_string())?;
-                    }
-                } else {
-                    if invert {
-                        ch.send_byps(line.to_string())?;
-                    } else {
-                        ch.send_keep(line.to_string())?;
-                    }
-                }
-                ch.send_keep(eol)?;
-            }
-            Err(e) => msg_error(&e.to_string()),
-        }
+}
+
+impl RegexLineSelector {
+    pub fn new(re: Regex, invert: bool) -> RegexLineSelector {
+        RegexLineSelector { re, invert }
     }
-    Ok(())
 }
 
-/// Bypassing particular strings based on Regular Expression ( -o -g )
-pub fn regex_proc(
-    ch: &mut PipeIntercepter,
-    line: &Vec<u8>,
-    re: &Regex,
+impl Selector for RegexLineSelector {
+    fn select(&mut self, record: &str) -> Vec<Span> {
+        line_spans(record, self.re.is_match(record) != self.invert)
+    }
+}
+
+/// Bypassing the lines around the matched lines as well ( -g with -A, -B, -C )
+///
+/// A line which is not matched is held until a matched line follows within `before` lines,
+/// or until `before` lines follow.
+pub struct ContextSelector {
+    matcher: Box<dyn Selector>, // Selects the matched lines (-g, -G)
+    before: usize,
+    after: usize,
     invert: bool,
-) -> Result<(), errors::ChunkSendError> {
-    let line = String::from_utf8_lossy(&line).to_string();
-    let mut left_index = 0;
-    let mut right_index;
-    for cap in re.find_iter(&line) {
-        right_index = cap.start();
-        let unmatched = &line[left_index..right_index];
-        let matched = &line[cap.start()..cap.end()];
-        // Ignore empty string.
-        // Regex "*" matches empty, but , in most situations,
-        // handling empty string is not helpful for users.
-        if !unmatched.is_empty() {
-            if !invert {
-                ch.send_keep(unmatched.to_string())?;
-            } else {
-                ch.send_byps(unmatched.to_string())?;
+    ahead: VecDeque<(Vec<u8>, Option<bool>)>, // Lines read ahead and whether they are acted on, once it is known
+    pending: usize, // Number of the lines at the end of `ahead` which are not known to be acted on
+    eof: bool,
+    selected: bool, // Whether the current line is acted on
+    left: usize,    // Number of lines to act on after the last matched line
+}
+
+impl ContextSelector {
+    pub fn new(matcher: Box<dyn Selector>, before: usize, after: usize, invert: bool) -> ContextSelector {
+        ContextSelector {
+            matcher,
+            before,
+            after,
+            invert,
+            ahead: VecDeque::with_capacity(before + 1),
+            pending: 0,
+            eof: false,
+            selected: false,
+            left: 0,
+        }
+    }
+
+    /// Read another line, and tell whether the lines read so far are acted on if it is known
+    fn read_ahead(&mut self, input: &mut dyn BufRead, line_end: u8) -> io::Result<()> {
+        let mut line = Vec::new();
+        if self.matcher.read_record(input, line_end, &mut line)? == 0 {
+            self.eof = true;
+            // No more matched lines follow
+            self.resolve(self.pending, false);
+            return Ok(());
+        }
+        let mut content = line.clone();
+        stringutils::trim_eol(&mut content);
+        let matched = !self.matcher.select(&String::from_utf8_lossy(&content)).is_empty();
+        self.ahead.push_back((line, None));
+        self.pending += 1;
+        if matched {
+            // The pending lines are within `before` lines before this line
+            self.resolve(self.pending, true);
+            self.left = self.after;
+        } else if self.left > 0 {
+            self.resolve(1, true);
+            self.left -= 1;
+        } else if self.pending > self.before {
+            // None of the next `before` lines is matched
+            self.resolve(1, false);
+        }
+        Ok(())
+    }
+
+    /// Tell whether the first `n` lines of the pending lines are acted on
+    fn resolve(&mut self, n: usize, selected: bool) {
+        let start = self.ahead.len() - self.pending;
+        for (_, s) in self.ahead.range_mut(start..start + n) {
+            *s = Some(selected);
+        }
+        self.pending -= n;
+    }
+}
+
+impl Selector for ContextSelector {
+    fn open(&mut self, input: Box<dyn Read + Send>, line_end: u8) -> io::Result<Box<dyn Read + Send>> {
+        self.ahead.clear();
+        self.pending = 0;
+        self.eof = false;
+        self.left = 0;
+        self.matcher.open(input, line_end)
+    }
+
+    /// A line is returned as soon as it is known to be acted on or not.
+    /// At most one line is read at a time so that the lines returned so far are flushed if the input is idle.
+    fn read_record(&mut self, input: &mut dyn BufRead, line_end: u8, buf: &mut Vec<u8>) -> io::Result<usize> {
+        if self.ahead.len() == self.pending && !self.eof {
+            self.read_ahead(input, line_end)?;
+        }
+        match self.ahead.front() {
+            // Whether the line is acted on depends on the next lines
+            Some((_, None)) => Err(io::ErrorKind::WouldBlock.into()),
+            Some(_) => {
+                let (line, selected) = self.ahead.pop_front().unwrap();
+                self.selected = selected == Some(true);
+                buf.extend_from_slice(&line);
+                Ok(line.len())
             }
+            None => Ok(0),
         }
-        if !invert {
-            ch.send_byps(matched.to_string())?;
-        } else {
-            ch.send_keep(matched.to_string())?;
+    }
+
+    fn select(&mut self, record: &str) -> Vec<Span> {
+        line_spans(record, self.selected != self.invert)
+    }
+}
+
+/// Bypassing particular lines based on the address of sed ( --sed )
+pub struct SedSelector {
+    address: Address,
+    nr: u64,    // number of read
+    last: bool, // Whether the current line is the last one
+    invert: bool,
+}
+
+impl SedSelector {
+    pub fn new(address: Address, invert: bool) -> SedSelector {
+        SedSelector {
+            address,
+            nr: 0,
+            last: false,
+            invert,
         }
-        left_index = cap.end();
     }
-    if left_index < line.len() {
-        let unmatched = &line[left_index..line.len()];
-        if !invert {
-            ch.send_keep(unmatched.to_string())?;
-        } else {
-            ch.send_byps(unmatched.to_string())?;
+}
+
+impl Selector for SedSelector {
+    fn open(&mut self, input: Box<dyn Read + Send>, _line_end: u8) -> io::Result<Box<dyn Read + Send>> {
+        self.address.reset();
+        self.nr = 0;
+        Ok(input)
+    }
+
+    fn read_record(&mut self, input: &mut dyn BufRead, line_end: u8, buf: &mut Vec<u8>) -> io::Result<usize> {
+        let n = input.read_until(line_end, buf)?;
+        // `$` waits for the next line to know whether the current one is the last
+        if self.address.needs_last() {
+            self.last = input.fill_buf()?.is_empty();
         }
+        Ok(n)
+    }
+
+    fn select(&mut self, record: &str) -> Vec<Span> {
+        self.nr += 1;
+        line_spans(record, self.address.matches(record, self.nr, self.last) != self.invert)
+    }
+}
+
+/// Bypassing particular strings based on Regular Expression ( -o -g )
+pub struct RegexSelector {
+    re: Regex,
+    invert: bool,
+}
+
+impl RegexSelector {
+    pub fn new(re: Regex, invert: bool) -> RegexSelector {
+        RegexSelector { re, invert }
+    }
+}
+
+impl Selector for RegexSelector {
+    fn select(&mut self, record: &str) -> Vec<Span> {
+        let matches = self.re.find_iter(record).map(|m| (m.start(), m.end()));
+        match_spans(matches, record.len(), self.invert)
     }
-    Ok(())
 }
 
 /// Bypassing character range ( -c )
-pub fn char_proc(
-    ch: &mut PipeIntercepter,
-    line: &Vec<u8>,
-    ranges: &Vec<list::ranges::Range>,
-) -> Result<(), errors::ChunkSendError> {
-    let line = String::from_utf8_lossy(&line).to_string();
-    let cs = line.chars();
-    let mut str_in = String::new();
-    let mut str_out = String::new();
-    let mut ri = 0;
-    let mut is_in;
-    let mut last_is_in = false;
-    // Merge consequent characters' range to execute commands as few times as possible.
-    for (i, c) in cs.enumerate() {
-        if ranges[ri].high < (i + 1) && (ri + 1) < ranges.len() {
-            ri += 1;
-        }
-        if ranges[ri].low <= (i + 1) && (i + 1) <= ranges[ri].high {
-            is_in = true;
-            str_in.push(c);
-        } else {
-            is_in = false;
-            str_out.push(c);
-        }
-        if is_in && !last_is_in {
-            ch.send_keep(str_out.to_string())?;
-            str_out.clear();
-        } else if !is_in && last_is_in {
-            ch.send_byps(str_in.to_string())?;
-            str_in.clear();
+pub struct CharSelector {
+    ranges: Vec<list::ranges::Range>,
+}
+
+impl CharSelector {
+    pub fn new(ranges: Vec<list::ranges::Range>) -> CharSelector {
+        CharSelector { ranges }
+    }
+}
+
+impl Selector for CharSelector {
+    fn select(&mut self, record: &str) -> Vec<Span> {
+        let ranges = &self.ranges;
+        let mut spans: Vec<Span> = Vec::new();
+        let mut ri = 0;
+        let mut last_is_in = false;
+        // Merge consequent characters' range to execute commands as few times as possible.
+        for (i, (pos, c)) in record.char_indices().enumerate() {
+            if ranges[ri].high < (i + 1) && (ri + 1) < ranges.len() {
+                ri += 1;
+            }
+            let is_in = ranges[ri].low <= (i + 1) && (i + 1) <= ranges[ri].high;
+            if is_in {
+                match spans.last_mut() {
+                    Some(span) if last_is_in => span.end = pos + c.len_utf8(),
+                    _ => spans.push(Span::new(pos, pos + c.len_utf8(), Index::None)),
+                }
+            }
+            last_is_in = is_in;
         }
-        last_is_in = is_in;
+        spans
     }
-    if last_is_in && !str_in.is_empty() {
-        ch.send_byps(str_in)?;
-    } else {
-        ch.send_keep(str_out)?;
+}
+
+/// Find the first target whose ranges include `i` ( -f ... ::: -f ... )
+/// `ris` holds current position in the ranges of each target, so `i` must not decrease.
+fn find_target(
+    targets: &[Vec<list::ranges::Range>],
+    ris: &mut [usize],
+    i: usize,
+) -> Option<usize> {
+    let mut found = None;
+    for (t, ranges) in targets.iter().enumerate() {
+        while ranges[ris[t]].high < i && (ris[t] + 1) < ranges.len() {
+            ris[t] += 1;
+        }
+        if found.is_none() && ranges[ris[t]].low <= i && i <= ranges[ris[t]].high {
+            found = Some(t);
+        }
     }
-    Ok(())
+    found
 }
 
 /// Bypassing white space separation ( -f )
-pub fn field_regex_proc(
-    ch: &mut PipeIntercepter,
-    line: &Vec<u8>,
-    re: &Regex,
-    ranges: &Vec<list::ranges::Range>,
-) -> Result<(), errors::ChunkSendError> {
-    let line = String::from_utf8_lossy(&line).to_string();
-    let mut i = 1; // current field index
-    let mut ri = 0;
-    let mut left_index = 0;
-    let mut right_index;
-    for cap in re.find_iter(&line) {
-        right_index = cap.start();
-        let field = &line[left_index..right_index]; // This can be empty string
-        let spaces = &line[cap.start()..cap.end()];
-        left_index = cap.end();
-        if ranges[ri].high < i && (ri + 1) < ranges.len() {
-            ri += 1;
-        }
-        if ranges[ri].low <= i && i <= ranges[ri].high {
-            ch.send_byps(field.to_string())?;
-        } else {
-            ch.send_keep(field.to_string())?;
-        }
-        ch.send_keep(spaces.to_string())?;
-        i += 1;
+pub struct FieldRegexSelector {
+    targets: Vec<Vec<list::ranges::Range>>,
+    re: Regex,
+}
+
+impl FieldRegexSelector {
+    pub fn new(targets: Vec<Vec<list::ranges::Range>>, re: Regex) -> FieldRegexSelector {
+        FieldRegexSelector { targets, re }
     }
-    // If line ends with delimiter, empty fields must be handled.
-    if left_index <= line.len() {
-        if ranges[ri].high < i && (ri + 1) < ranges.len() {
-            ri += 1;
+}
+
+impl Selector for FieldRegexSelector {
+    fn select(&mut self, record: &str) -> Vec<Span> {
+        let mut spans = Vec::new();
+        let mut ris = vec![0; self.targets.len()];
+        let mut i = 1; // current field index
+        let mut left_index = 0;
+        for cap in self.re.find_iter(record) {
+            // Field can be empty string
+            if let Some(t) = find_target(&self.targets, &mut ris, i) {
+                spans.push(Span::new(left_index, cap.start(), Index::Field(i)).to(t));
+            }
+            left_index = cap.end();
+            i += 1;
         }
-        // filed is empty if line ends with delimiter
-        let field = &line[left_index..line.len()];
-        if ranges[ri].low <= i && i <= ranges[ri].high {
-            ch.send_byps(field.to_string())?;
-        } else {
-            ch.send_keep(field.to_string())?;
+        // If line ends with delimiter, empty fields must be handled.
+        if let Some(t) = find_target(&self.targets, &mut ris, i) {
+            spans.push(Span::new(left_index, record.len(), Index::Field(i)).to(t));
         }
+        spans
     }
-    Ok(())
 }
 
 /// Bypassing field separation ( -f -d )
-pub fn field_proc(
-    ch: &mut PipeIntercepter,
-    line: &Vec<u8>,
-    delim: &str,
-    ranges: &Vec<list::ranges::Range>,
-) -> Result<(), errors::ChunkSendError> {
-    let line = String::from_utf8_lossy(&line).to_string();
-    let chunks = line.split(delim);
-    let mut ri = 0;
-    for (i, chunk) in chunks.enumerate() {
-        if i > 0 {
-            ch.send_keep(delim.to_string())?;
-        }
-        if ranges[ri].high < (i + 1) && (ri + 1) < ranges.len() {
-            ri += 1;
-        }
-        if ranges[ri].low <= (i + 1) && (i + 1) <= ranges[ri].high {
-            // Should empty filed sent as empty string ? Discussion is needed.
-            // But author(@greymd) believes empty string is good to be sent.
-            // Because teip can be used as simple CSV file editor if it is allowed!
-            // ```
-            // $ printf ',,,\n,,,\n,,,\n' | teip -d, -f1- -- seq 12
-            // 1,2,3,4
-            // 5,6,7,8
-            // 9,10,11,12
-            // ```
-            ch.send_byps(chunk.to_string())?;
-        } else {
-            ch.send_keep(chunk.to_string())?;
+pub struct FieldSelector {
+    targets: Vec<Vec<list::ranges::Range>>,
+    delim: String,
+}
+
+impl FieldSelector {
+    pub fn new(targets: Vec<Vec<list::ranges::Range>>, delim: String) -> FieldSelector {
+        FieldSelector { targets, delim }
+    }
+}
+
+impl Selector for FieldSelector {
+    fn select(&mut self, record: &str) -> Vec<Span> {
+        let mut spans = Vec::new();
+        let mut ris = vec![0; self.targets.len()];
+        let mut left_index = 0;
+        for (i, chunk) in record.split(self.delim.as_str()).enumerate() {
+            if let Some(t) = find_target(&self.targets, &mut ris, i + 1) {
+                // Should empty filed sent as empty string ? Discussion is needed.
+                // But author(@greymd) believes empty string is good to be sent.
+                // Because teip can be used as simple CSV file editor if it is allowed!
+                // ```
+                // $ printf ',,,\n,,,\n,,,\n' | teip -d, -f1- -- seq 12
+                // 1,2,3,4
+                // 5,6,7,8
+                // 9,10,11,12
+                // ```
+                spans.push(Span::new(left_index, left_index + chunk.len(), Index::Field(i + 1)).to(t));
+            }
+            left_index += chunk.len() + self.delim.len();
         }
+        spans
     }
-    Ok(())
 }
 
 /// External execution for match offloading ( -e )
 ///  Example:
-///  ``````````````````````````````````````````````````````````````````
+///  ``````````````````````````````````````````````````````````````````text
 ///  $ echo -e "AAA\nBBB\nCCC\nDDD\nEEE\n" | teip -e 'grep -n "[ACE]"'
 ///  [AAA]
 ///  BBB
@@ -256,6 +389,7 @@ pub fn field_proc(
 ///  [EEE]
 ///  ``````````````````````````````````````````````````````````````````
 ///
+/// ```text
 ///                          [ stdin ] "AAA\nBBB\nCCC\n..."
 ///                              │
 ///                              │
@@ -263,27 +397,27 @@ pub fn field_proc(
 ///                         │  _tee_thread  ├──────┐
 ///                         └────┬──────────┘      │
 ///                              │                 │
-///                          (rx_stdin1)           │
+///                       (pipeline_stdin)         │
 ///                          "AAA\nBBB\nCCC\n..."  │
 ///                              │                 │
-///                              │           (rx_stdin2)
+///                              │             (window)
 ///                              │            "AAA\nBBB\nCCC\n..."
 ///                         ┌────▼──────────┐      │
-/// (exoffload_pipeline)────►   _ex_thread  │      │
+/// (exoffload_pipeline)────►   pipeline    │      │
 /// "grep -n '[ACE]'"       └────┬──────────┘      │
 ///                              │                 │
-///                         (rx_messy_numbers)     │
+///                         (messy_numbers)        │
 ///                         "1:AAA\n3:CCC\n..."    │
 ///                              │                 │
 ///                         ┌────▼──────────┐      │
-///                         │  _num_thread  │      │
+///                         │  _num_thread  ├──────┤ resolve: lines up to 3 can be taken
 ///                         └────┬──────────┘      │
 ///                              │                 │
 ///                            (rx_numbers)        │
 ///                            1,│3, 5 ...as u64   │
 ///                              │                 │
 ///                         ┌────┴─────────────────┴──────────┐  ┌─────────────────────┐
-///                         │ Main thread > exoffload_proc    │  │ PipeIntercepter     │
+///                         │ Main thread > ExoffloadSelector │  │ PipeIntercepter     │
 ///                         ├────┬─────────────────┬──────────┤  ├─────────────────────┤
 ///                         │    │                 │          │  │                     │
 ///                         │    │              ┌──▼────────┐ │  │                     │
@@ -301,135 +435,360 @@ pub fn field_proc(
 ///                         │  │   │            │           │ │  │    └───────────┘    │
 ///                         │  └───┘            └───────────┘ │  │                     │
 ///                         └─────────────────────────────────┘  └─────────────────────┘
-pub fn exoffload_proc(
-    ch: &mut PipeIntercepter,
-    exoffload_pipeline: &str,
+/// ```
+///
+/// The window keeps only the lines which the main thread has not taken yet.
+/// If the input is a regular file, the pipeline reads the file by itself and nothing is kept.
+pub struct ExoffloadSelector {
+    pipeline: String,
+    splitter: FieldSplitter, // Splits the line for "N:fK"
+    reorder: usize,          // Number of the numbers kept to sort them
     invert: bool,
-    line_end: u8,
-) -> Result<(), errors::ChunkSendError> {
-    let stdin = io::stdin();
-    let (rx_stdin1, rx_stdin2, _tee_thread) = spawnutils::tee(stdin, line_end)
-            .unwrap_or_else(|e| error_exit(&e.to_string()));
-    let (rx_messy_numbers, _ex_thread) = spawnutils::exec_pipeline_mpsc_input(exoffload_pipeline, rx_stdin1)
-            .unwrap_or_else(|e| error_exit(&e.to_string()));
-    let (rx_numbers, _num_thread) = spawnutils::clean_numbers(rx_messy_numbers, line_end);
-    let mut nr: u64 = 0;     // number of read
-    let mut pos: u64 = 0;    // position of printable numbers
-    let mut last_pos: u64 = pos;
-    let mut expect_new_numbers: bool = true;
-    loop {
-        nr += 1;
-        // Load line from stdin
-        let mut buf = match rx_stdin2.recv() {
-            Ok(b) => b,
-            Err(_) => {
-                ch.send_eof()?;
-                break;
-            },
+    rx_numbers: Option<Receiver<(u64, Region)>>,
+    nr: u64,                // number of read
+    pos: u64,               // position of printable numbers
+    region: Option<Region>, // Region of the line `pos`, which is not applied yet
+    last_pos: u64,
+}
+
+impl ExoffloadSelector {
+    pub fn new(pipeline: String, splitter: FieldSplitter, reorder: usize, invert: bool) -> ExoffloadSelector {
+        ExoffloadSelector {
+            pipeline,
+            splitter,
+            reorder,
+            invert,
+            rx_numbers: None,
+            nr: 0,
+            pos: 0,
+            region: None,
+            last_pos: 0,
+        }
+    }
+
+    fn start(&mut self, rx_numbers: Receiver<(u64, Region)>) {
+        self.rx_numbers = Some(rx_numbers);
+        self.nr = 0;
+        self.pos = 0;
+        self.region = None;
+        self.last_pos = 0;
+    }
+
+    /// Byte range of the region in the record. None if it is out of the record.
+    fn byte_range(&self, record: &str, region: &Region) -> Option<(usize, usize, Index)> {
+        // Offset of the `n`-th character counted from 0
+        let char_pos = |n: usize| record.char_indices().nth(n).map_or(record.len(), |(pos, _)| pos);
+        // A character is not divided
+        let floor = |mut i: usize| {
+            while !record.is_char_boundary(i) {
+                i -= 1;
+            }
+            i
+        };
+        let ceil = |mut i: usize| {
+            while !record.is_char_boundary(i) {
+                i += 1;
+            }
+            i
+        };
+        let (start, end, index) = match *region {
+            Region::Line => (0, record.len(), Index::None),
+            Region::Chars(start, end) => (char_pos(start.max(1) - 1), char_pos(end), Index::None),
+            Region::Bytes(start, end) => (floor((start.max(1) - 1).min(record.len())), ceil(end.min(record.len())), Index::None),
+            Region::Field(k) => {
+                let (start, end) = *self.splitter.spans(record).get(k.checked_sub(1)?)?;
+                return Some((start, end, Index::Field(k)));
+            }
         };
-        let eol = stringutils::trim_eol(&mut buf);
-        let line = String::from_utf8_lossy(&buf).to_string();
-        // Try to detect printable line numbers which is bigger than current read line
-        while expect_new_numbers && pos < nr {
-            pos = match rx_numbers.recv() {
-                Ok(n) => n,
-                Err(_) => {
+        if start < end {
+            Some((start, end, index))
+        } else {
+            None
+        }
+    }
+}
+
+impl Selector for ExoffloadSelector {
+    fn open(&mut self, input: Box<dyn Read + Send>, line_end: u8) -> io::Result<Box<dyn Read + Send>> {
+        let (pipeline_stdin, messy_numbers) = exoffload::exec_pipeline(&self.pipeline, None)?;
+        let (window, stdin, _tee_thread) = exoffload::tee(input, line_end, pipeline_stdin);
+        let (rx_numbers, _num_thread) = exoffload::clean_numbers(messy_numbers, line_end, self.reorder, Some(window));
+        self.start(rx_numbers);
+        Ok(stdin)
+    }
+
+    /// A regular file is read by the pipeline as well, instead of passing the lines kept in memory.
+    #[cfg(unix)]
+    fn open_file(&mut self, file: std::fs::File, line_end: u8) -> io::Result<Box<dyn Read + Send>> {
+        if !file.metadata().map(|m| m.is_file()).unwrap_or(false) {
+            return self.open(Box::new(file), line_end);
+        }
+        let pipeline_stdin = file.try_clone()?;
+        let stdin = exoffload::FileReader::new(file)?;
+        let (_, messy_numbers) = exoffload::exec_pipeline(&self.pipeline, Some(pipeline_stdin))?;
+        let (rx_numbers, _num_thread) = exoffload::clean_numbers(messy_numbers, line_end, self.reorder, None);
+        self.start(rx_numbers);
+        Ok(Box::new(stdin))
+    }
+
+    fn select(&mut self, record: &str) -> Vec<Span> {
+        self.nr += 1;
+        let mut regions = Vec::new();
+        loop {
+            if self.pos == self.nr {
+                regions.extend(self.region.take());
+            }
+            // Try to detect printable line numbers which is bigger than current read line.
+            // Unless the entire line is chosen, the following numbers may choose other regions of the line.
+            if self.pos > self.nr || regions.contains(&Region::Line) {
+                break;
+            }
+            let (pos, region) = match self.rx_numbers.as_ref().map(|rx| rx.recv()) {
+                Some(Ok(n)) => n,
+                _ => {
                     // Once queue got disconnected, new numbers is no longer expected.
-                    expect_new_numbers = false;
+                    self.rx_numbers = None;
                     break;
                 },
             };
-            if pos < last_pos {
-                msg_error(format!("WARN: pipeline must print numbers in ascending order: order {} -> {} found", last_pos, pos).as_ref());
+            if pos < self.last_pos {
+                eprintln!("{}: WARN: pipeline must print numbers in ascending order: order {} -> {} found (see --reorder)", CMD, self.last_pos, pos);
             }
-            last_pos = pos;
+            self.pos = pos;
+            self.region = Some(region);
+            self.last_pos = pos;
         }
-        if pos == nr {
-            if invert {
-                ch.send_keep(line.to_string())?;
-            } else {
-                ch.send_byps(line.to_string())?;
-            }
-        } else {
-            if invert {
-                ch.send_byps(line.to_string())?;
-            } else {
-                ch.send_keep(line.to_string())?;
+        if regions.is_empty() || regions.contains(&Region::Line) {
+            return line_spans(record, regions.is_empty() == self.invert);
+        }
+        let mut ranges: Vec<(usize, usize, Index)> = regions.iter().filter_map(|r| self.byte_range(record, r)).collect();
+        ranges.sort_by_key(|&(start, end, _)| (start, end));
+        // Overlapping regions are a single hole
+        let mut merged: Vec<(usize, usize, Index)> = Vec::new();
+        for (start, end, index) in ranges {
+            match merged.last_mut() {
+                Some(last) if start < last.1 || (start, end) == (last.0, last.1) => last.1 = last.1.max(end),
+                _ => merged.push((start, end, index)),
             }
         }
-        ch.send_keep(eol)?;
+        if self.invert {
+            return match_spans(merged.into_iter().map(|(start, end, _)| (start, end)), record.len(), true);
+        }
+        merged.into_iter().map(|(start, end, index)| Span::new(start, end, index)).collect()
     }
-    Ok(())
 }
 
-/// Process CSV align with RFC 4180 (--csv)
-///
-/// This function is called from main() when --csv option is specified.
-pub fn csv_proc(
-    ch: &mut PipeIntercepter,
-    ranges: &Vec<list::ranges::Range>,
-    line_end: u8,
-    flag_solid: bool,
-    ) -> Result<(), errors::ChunkSendError> {
-    use super::csv::parser::Parser;
+/// Read a CSV record, which continues to the next line while a quoted field is not closed.
+/// Also returns the length of the last line end if it belongs to the unclosed field.
+fn read_csv_record(input: &mut dyn BufRead, line_end: u8, buf: &mut Vec<u8>) -> io::Result<(usize, usize)> {
     let mut parser = Parser::new();
-    let mut str_byps = String::new();
-    let mut str_keep = String::new();
-    let mut is_byps;
-    let line_end_char = line_end as char;
-    let mut last_is_byps = false;
-    let mut ri = 0;
-    let stdin = io::stdin();
+    let mut total = 0;
     loop {
-        let mut buf = Vec::with_capacity(DEFAULT_CAP);
-        match stdin.lock().read_until(line_end, &mut buf) {
-            Ok(n) => {
-                let line = String::from_utf8_lossy(&buf).to_string();
-                let cs = line.chars();
-                // Check each byte in the line
-                for (_, c) in cs.enumerate() {
-                    parser.interpret(c);
-                    if parser.is_in_field() && ( flag_solid || c != line_end_char ) {
-                        let field = parser.field() as usize;
-                        // check if the field is in the range
-                        if ranges[ri].high < field && (ri + 1) < ranges.len() {
-                            ri += 1;
-                        }
-                        if ranges[ri].low <= field && field <= ranges[ri].high {
-                            is_byps = true;
-                            str_byps.push(c);
-                        } else {
-                            is_byps = false;
-                            str_keep.push(c);
-                        }
-                    } else {
-                        is_byps = false;
-                        str_keep.push(c);
-                    }
-                    if is_byps && !last_is_byps {
-                        ch.send_keep(str_keep.to_string())?;
-                        str_keep.clear();
-                    } else if !is_byps && last_is_byps {
-                        ch.send_byps(str_byps.to_string())?;
-                        str_byps.clear();
+        let start = buf.len();
+        let n = input.read_until(line_end, buf)?;
+        if n == 0 {
+            // The last line end belongs to the quoted field
+            return Ok((total, stringutils::trim_eol(&mut buf.clone()).len()));
+        }
+        total += n;
+        String::from_utf8_lossy(&buf[start..]).chars().for_each(|c| {
+            parser.interpret(c);
+        });
+        match parser.state() {
+            NfaState::InQuotedField | NfaState::InEscapedQuote => {}
+            _ => return Ok((total, 0)),
+        }
+    }
+}
+
+/// Process CSV align with RFC 4180 (--csv)
+///
+/// A record continues to the next line while a quoted field is not closed.
+pub struct CsvSelector {
+    targets: Vec<Vec<list::ranges::Range>>,
+    unclosed: usize, // Length of the line end if the input ends in a quoted field
+}
+
+impl CsvSelector {
+    pub fn new(targets: Vec<Vec<list::ranges::Range>>) -> CsvSelector {
+        CsvSelector { targets, unclosed: 0 }
+    }
+}
+
+impl Selector for CsvSelector {
+    fn read_record(&mut self, input: &mut dyn BufRead, line_end: u8, buf: &mut Vec<u8>) -> io::Result<usize> {
+        let (total, unclosed) = read_csv_record(input, line_end, buf)?;
+        self.unclosed = unclosed;
+        Ok(total)
+    }
+
+    fn select(&mut self, record: &str) -> Vec<Span> {
+        let mut parser = Parser::new();
+        let mut spans: Vec<Span> = Vec::new();
+        let mut ris = vec![0; self.targets.len()];
+        let mut last_is_byps = false;
+        // Check each character in the record
+        for (pos, c) in record.char_indices() {
+            parser.interpret(c);
+            let mut is_byps = false;
+            if parser.is_in_field() {
+                let field = parser.field() as usize;
+                // check if the field is in the range
+                if let Some(t) = find_target(&self.targets, &mut ris, field) {
+                    is_byps = true;
+                    if !last_is_byps {
+                        spans.push(Span::new(pos, pos, Index::Field(field)).to(t));
                     }
-                    last_is_byps = is_byps;
                 }
-                ri = 0;
-                if n == 0 {
-                    // If end of file does not have line feed, this part sends the remaining chunk
-                    if last_is_byps && !str_byps.is_empty() {
-                        ch.send_byps(str_byps)?;
+            }
+            if is_byps {
+                if let Some(span) = spans.last_mut() {
+                    span.end = pos + c.len_utf8();
+                }
+            }
+            last_is_byps = is_byps;
+        }
+        if last_is_byps {
+            if let Some(span) = spans.last_mut() {
+                span.end += self.unclosed;
+            }
+        }
+        spans
+    }
+}
+
+/// How --where and -e split a record into fields, which is the same as -f
+pub enum FieldSplitter {
+    Regex(Regex),      // -D, or white spaces by default
+    Delimiter(String), // -d
+    Csv,               // --csv
+}
+
+impl FieldSplitter {
+    /// Byte ranges of the fields. A field of CSV includes the quotes.
+    pub fn spans(&self, record: &str) -> Vec<(usize, usize)> {
+        match self {
+            FieldSplitter::Regex(re) => {
+                let mut spans = Vec::new();
+                let mut left_index = 0;
+                for m in re.find_iter(record) {
+                    spans.push((left_index, m.start()));
+                    left_index = m.end();
+                }
+                spans.push((left_index, record.len()));
+                spans
+            }
+            FieldSplitter::Delimiter(delim) => {
+                let mut spans = Vec::new();
+                let mut left_index = 0;
+                for chunk in record.split(delim.as_str()) {
+                    spans.push((left_index, left_index + chunk.len()));
+                    left_index += chunk.len() + delim.len();
+                }
+                spans
+            }
+            FieldSplitter::Csv => {
+                let mut parser = Parser::new();
+                let mut spans = Vec::new();
+                let (mut start, mut end) = (0, 0); // current field
+                for (pos, c) in record.char_indices() {
+                    parser.interpret(c);
+                    while spans.len() + 1 < parser.field() as usize {
+                        spans.push((start, end));
+                        // Next field starts after the delimiter
+                        start = if parser.is_in_field() { pos } else { pos + c.len_utf8() };
+                        end = start;
                     }
-                    if !str_keep.is_empty() {
-                        ch.send_keep(str_keep)?;
+                    if parser.is_in_field() {
+                        end = pos + c.len_utf8();
                     }
-                    ch.send_eof()?;
-                    break;
+                }
+                spans.push((start, end));
+                spans
+            }
+        }
+    }
+
+    pub fn split<'a>(&self, record: &'a str) -> Vec<Cow<'a, str>> {
+        let fields = self.spans(record).into_iter().map(|(start, end)| &record[start..end]);
+        match self {
+            FieldSplitter::Csv => fields.map(|f| Cow::Owned(unquote(f))).collect(),
+            _ => fields.map(Cow::Borrowed).collect(),
+        }
+    }
+}
+
+/// "a""b" => a"b
+fn unquote(field: &str) -> String {
+    match field.strip_prefix('"').and_then(|f| f.strip_suffix('"')) {
+        Some(f) => f.replace("\"\"", "\""),
+        None => field.to_string(),
+    }
+}
+
+/// Bypassing the lines which satisfy the expression ( --where )
+///
+/// If another selector is given, its holes are made only in those lines.
+pub struct WhereSelector {
+    expr: Expr,
+    splitter: FieldSplitter,
+    selector: Option<Box<dyn Selector>>,
+    invert: bool,
+    nr: u64, // number of read
+}
+
+impl WhereSelector {
+    pub fn new(expr: Expr, splitter: FieldSplitter, selector: Option<Box<dyn Selector>>, invert: bool) -> WhereSelector {
+        WhereSelector {
+            expr,
+            splitter,
+            selector,
+            invert,
+            nr: 0,
+        }
+    }
+}
+
+impl Selector for WhereSelector {
+    fn open(&mut self, input: Box<dyn Read + Send>, line_end: u8) -> io::Result<Box<dyn Read + Send>> {
+        self.nr = 0;
+        match self.selector {
+            Some(ref mut s) => s.open(input, line_end),
+            None => Ok(input),
+        }
+    }
+
+    fn open_file(&mut self, file: std::fs::File, line_end: u8) -> io::Result<Box<dyn Read + Send>> {
+        self.nr = 0;
+        match self.selector {
+            Some(ref mut s) => s.open_file(file, line_end),
+            None => Ok(Box::new(file)),
+        }
+    }
+
+    fn read_record(&mut self, input: &mut dyn BufRead, line_end: u8, buf: &mut Vec<u8>) -> io::Result<usize> {
+        match self.selector {
+            Some(ref mut s) => s.read_record(input, line_end, buf),
+            None if matches!(self.splitter, FieldSplitter::Csv) => read_csv_record(input, line_end, buf).map(|(n, _)| n),
+            None => input.read_until(line_end, buf),
+        }
+    }
+
+    fn select(&mut self, record: &str) -> Vec<Span> {
+        self.nr += 1;
+        let fields = self.splitter.split(record);
+        let satisfied = self.expr.matches(record, &fields, self.nr);
+        match self.selector {
+            // The selector sees every line to keep its state (e.g. line numbers of -l)
+            Some(ref mut s) => {
+                let spans = s.select(record);
+                if satisfied {
+                    spans
+                } else {
+                    vec![]
                 }
             }
-            Err(e) => msg_error(&e.to_string()),
+            None => line_spans(record, satisfied != self.invert),
         }
     }
-    Ok(())
 }
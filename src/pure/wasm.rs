@@ -0,0 +1,13 @@
+use super::super::CMD;
+
+pub struct Plugin;
+
+impl Plugin {
+    pub fn new(_path: &str) -> Result<Plugin, String> {
+        Err(format!("This build is not enabled 'wasm'. Build {} with --features wasm", CMD))
+    }
+
+    pub fn apply(&mut self, _input: &str) -> Result<String, String> {
+        Ok("".to_string())
+    }
+}
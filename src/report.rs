@@ -0,0 +1,137 @@
+use teip::{Index, Location};
+use std::io::{self, Write};
+
+/// Prints the chunks in another form instead of as they are (--diff, --dry-run-format)
+pub trait Report {
+    /// A string under the masking tape
+    fn keep(&mut self, msg: &str, out: &mut dyn Write) -> io::Result<()>;
+
+    /// A string in the hole, the string which fills the hole and where the hole is
+    fn hole(&mut self, original: &str, result: &str, loc: &Location, out: &mut dyn Write) -> io::Result<()>;
+
+    /// End of the input
+    fn finish(&mut self, out: &mut dyn Write) -> io::Result<()>;
+}
+
+/// Prints a JSON object for each hole instead of the input (--dry-run-format json)
+///
+/// `{"file":"-","seq":1,"line":2,"offset":{"byte":6,"char":6},"column":{"byte":2,"char":2},"field":null,"match":1,"text":"12"}`
+/// The offsets are counted from 0 at the beginning of the input, and the columns at the beginning of the line.
+pub struct JsonReport {
+    file: String,
+    line_end: char,
+    chars: u64,         // Number of the characters so far
+    column: (u64, u64), // Number of the bytes and the characters since the last line end
+}
+
+impl JsonReport {
+    /// `file` is the path of the file, or "-" for the standard input
+    pub fn new(file: &str, line_end: u8) -> JsonReport {
+        JsonReport {
+            file: file.to_string(),
+            line_end: line_end as char,
+            chars: 0,
+            column: (0, 0),
+        }
+    }
+
+    fn advance(&mut self, msg: &str) {
+        let chars = msg.chars().count() as u64;
+        self.chars += chars;
+        self.column = match msg.rfind(self.line_end) {
+            Some(i) => {
+                let rest = &msg[i + 1..];
+                (rest.len() as u64, rest.chars().count() as u64)
+            }
+            None => (self.column.0 + msg.len() as u64, self.column.1 + chars),
+        };
+    }
+}
+
+impl Report for JsonReport {
+    fn keep(&mut self, msg: &str, _out: &mut dyn Write) -> io::Result<()> {
+        self.advance(msg);
+        Ok(())
+    }
+
+    fn hole(&mut self, original: &str, _result: &str, loc: &Location, out: &mut dyn Write) -> io::Result<()> {
+        let (field, matched) = match loc.index {
+            Index::Field(n) => (n.to_string(), "null".to_string()),
+            Index::Match(n) => ("null".to_string(), n.to_string()),
+            Index::None => ("null".to_string(), "null".to_string()),
+        };
+        writeln!(
+            out,
+            "{{\"file\":{},\"seq\":{},\"line\":{},\"offset\":{{\"byte\":{},\"char\":{}}},\"column\":{{\"byte\":{},\"char\":{}}},\"field\":{},\"match\":{},\"text\":{}}}",
+            json_string(&self.file),
+            loc.seq,
+            loc.line,
+            loc.byte_offset,
+            self.chars,
+            self.column.0,
+            self.column.1,
+            field,
+            matched,
+            json_string(original),
+        )?;
+        self.advance(original);
+        Ok(())
+    }
+
+    fn finish(&mut self, _out: &mut dyn Write) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Quote the string as a JSON string
+fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn loc(seq: u64, line: u64, byte_offset: u64, index: Index) -> Location {
+        Location { seq, line, byte_offset, index }
+    }
+
+    #[test]
+    fn test_json_report() {
+        let mut report = JsonReport::new("-", b'\n');
+        let mut out = Vec::new();
+        report.keep("αβ ", &mut out).unwrap();
+        report.hole("x", "", &loc(1, 1, 5, Index::Field(2)), &mut out).unwrap();
+        report.keep("\nab", &mut out).unwrap();
+        report.hole("\"γ\"", "", &loc(2, 2, 9, Index::Match(1)), &mut out).unwrap();
+        report.finish(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            concat!(
+                "{\"file\":\"-\",\"seq\":1,\"line\":1,\"offset\":{\"byte\":5,\"char\":3},\"column\":{\"byte\":5,\"char\":3},",
+                "\"field\":2,\"match\":null,\"text\":\"x\"}\n",
+                "{\"file\":\"-\",\"seq\":2,\"line\":2,\"offset\":{\"byte\":9,\"char\":7},\"column\":{\"byte\":2,\"char\":2},",
+                "\"field\":null,\"match\":1,\"text\":\"\\\"γ\\\"\"}\n",
+            )
+        );
+    }
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!(json_string("a\"b\\c\nd\u{1}"), "\"a\\\"b\\\\c\\nd\\u0001\"");
+    }
+}
@@ -0,0 +1,104 @@
+use teip::{Index, Location};
+use rhai::{Dynamic, Engine, Scope, AST};
+
+/// Limits of the script for each hole, so that an endless loop or recursion stops with an error
+const MAX_OPERATIONS: u64 = 1_000_000;
+const MAX_CALL_LEVELS: usize = 32;
+const MAX_EXPR_DEPTH: usize = 64;
+const MAX_FUNCTION_EXPR_DEPTH: usize = 32;
+
+/// Rhai script evaluated for each hole (--script, --script-file)
+///
+/// The script receives the string in the hole as `x` and its location as
+/// `nr`, `seq`, `offset`, `field` and `match_no`. `field` and `match_no` are `()`
+/// unless the hole is made by -f or -og. The value of the last expression is printed
+/// instead of the hole.
+///   $ echo "abc def" | teip -f 2 --script 'x.to_upper() + "@" + nr'
+///   abc DEF@1
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+}
+
+impl Script {
+    pub fn new(src: &str) -> Result<Script, String> {
+        let mut engine = Engine::new();
+        engine
+            .set_max_operations(MAX_OPERATIONS)
+            .set_max_call_levels(MAX_CALL_LEVELS)
+            .set_max_expr_depths(MAX_EXPR_DEPTH, MAX_FUNCTION_EXPR_DEPTH);
+        let ast = engine
+            .compile(src)
+            .map_err(|e| format!("Failed to compile the script: {}", e))?;
+        Ok(Script {
+            engine,
+            ast,
+            scope: Scope::new(),
+        })
+    }
+
+    /// Evaluate the script with the string in the hole.
+    pub fn apply(&mut self, input: &str, loc: &Location) -> Result<String, String> {
+        let (field, match_no) = match loc.index {
+            Index::Field(n) => (Dynamic::from(n as i64), Dynamic::UNIT),
+            Index::Match(n) => (Dynamic::UNIT, Dynamic::from(n as i64)),
+            Index::None => (Dynamic::UNIT, Dynamic::UNIT),
+        };
+        // Variables declared by the script are discarded for each hole.
+        self.scope.clear();
+        self.scope
+            .push("x", input.to_string())
+            .push("nr", loc.line as i64)
+            .push("seq", loc.seq as i64)
+            .push("offset", loc.byte_offset as i64)
+            .push_dynamic("field", field)
+            .push_dynamic("match_no", match_no);
+        let result = self
+            .engine
+            .eval_ast_with_scope::<Dynamic>(&mut self.scope, &self.ast)
+            .map_err(|e| format!("Script error: {}", e))?;
+        if result.is_unit() {
+            return Ok("".to_string());
+        }
+        Ok(result.to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn loc(index: Index) -> Location {
+        Location { seq: 3, line: 2, byte_offset: 10, index }
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut s = Script::new(r#"x.to_upper() + "@" + nr"#).unwrap();
+        assert_eq!(s.apply("abc", &loc(Index::None)), Ok("ABC@2".to_string()));
+        let mut s = Script::new("`${seq}:${offset}:${field}:${match_no}`").unwrap();
+        assert_eq!(s.apply("abc", &loc(Index::Field(4))), Ok("3:10:4:".to_string()));
+        assert_eq!(s.apply("abc", &loc(Index::Match(1))), Ok("3:10::1".to_string()));
+        let mut s = Script::new("let y = x.len(); y * 2").unwrap();
+        assert_eq!(s.apply("abc", &loc(Index::None)), Ok("6".to_string()));
+        assert_eq!(s.apply("abcd", &loc(Index::None)), Ok("8".to_string()));
+    }
+
+    #[test]
+    fn test_error() {
+        assert!(Script::new("x +").is_err());
+        let mut s = Script::new("x.no_such_function()").unwrap();
+        assert!(s.apply("abc", &loc(Index::None)).is_err());
+    }
+
+    #[test]
+    fn test_limits() {
+        let mut s = Script::new("loop {}").unwrap();
+        assert!(s.apply("abc", &loc(Index::None)).is_err());
+        let mut s = Script::new("fn f(n) { f(n + 1) } f(0)").unwrap();
+        assert!(s.apply("abc", &loc(Index::None)).is_err());
+        let deep = format!("{}x{}", "(".repeat(1000), ")".repeat(1000));
+        assert!(Script::new(&deep).is_err());
+    }
+}
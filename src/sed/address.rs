@@ -0,0 +1,463 @@
+// Evaluator of the address language of sed (--sed), which is compatible with GNU sed.
+// See "Selecting lines with sed" in the manual of GNU sed.
+use regex::Regex;
+
+/// Single address
+#[derive(Clone, Debug)]
+enum Addr {
+    Line(u64),       // 3
+    Step(u64, u64),  // first~step
+    Last,            // $
+    Regex(Regex),    // /re/, \%re%
+    Zero,            // 0 of 0,/re/
+}
+
+/// End of the range
+#[derive(Clone, Debug)]
+enum End {
+    Addr(Addr),      // addr1,addr2
+    Plus(u64),       // addr1,+N
+    Multiple(u64),   // addr1,~N
+}
+
+/// Selects lines by the address of sed like `/BEGIN/,/END/`, `1~3`, `$!`.
+#[derive(Clone, Debug)]
+pub struct Address {
+    first: Option<Addr>, // None selects every line
+    end: Option<End>,
+    negated: bool,
+    active: bool,  // Whether the range has started and not ended yet
+    end_line: u64, // Last line of the active range if `end` is a line number
+}
+
+impl Address {
+    /// Parse the address. `regex` compiles the regular expressions which are translated from
+    /// the basic regular expressions of sed.
+    pub fn parse<F>(script: &str, regex: F) -> Result<Address, String>
+    where
+        F: Fn(&str) -> Result<Regex, String>,
+    {
+        let err = |msg: &str| format!("Invalid address '{}': {}", script, msg);
+        let mut p = Parser {
+            chars: script.chars().collect(),
+            pos: 0,
+        };
+        p.skip_ws();
+        let first = if p.peek().is_none() || p.peek() == Some('!') {
+            None
+        } else {
+            Some(p.addr(&regex).map_err(|e| err(&e))?)
+        };
+        p.skip_ws();
+        let mut end = None;
+        if first.is_some() && p.eat(',') {
+            p.skip_ws();
+            end = Some(match p.peek() {
+                Some('+') => End::Plus(p.next_number().map_err(|e| err(&e))?),
+                Some('~') => End::Multiple(p.next_number().map_err(|e| err(&e))?),
+                _ => match p.addr(&regex).map_err(|e| err(&e))? {
+                    Addr::Step(..) => return Err(err("first~step cannot be the end of the range")),
+                    Addr::Zero => return Err(err("invalid usage of line address 0")),
+                    a => End::Addr(a),
+                },
+            });
+            p.skip_ws();
+        }
+        let negated = p.eat('!');
+        p.skip_ws();
+        if let Some(c) = p.peek() {
+            return Err(err(&format!("unexpected `{}'", c)));
+        }
+        if negated && first.is_none() {
+            return Err(err("missing address before `!'"));
+        }
+        match (&first, &end) {
+            (Some(Addr::Zero), Some(End::Addr(Addr::Regex(_)))) => {}
+            (Some(Addr::Zero), _) => return Err(err("invalid usage of line address 0")),
+            _ => {}
+        }
+        let mut address = Address {
+            first,
+            end,
+            negated,
+            active: false,
+            end_line: 0,
+        };
+        address.reset();
+        Ok(address)
+    }
+
+    /// Forget the range before reading another input.
+    pub fn reset(&mut self) {
+        // 0,/re/ has already started before the first line
+        self.active = matches!(self.first, Some(Addr::Zero));
+        self.end_line = 0;
+    }
+
+    /// Whether `matches` needs to know the last line ($)
+    pub fn needs_last(&self) -> bool {
+        matches!(self.first, Some(Addr::Last)) || matches!(self.end, Some(End::Addr(Addr::Last)))
+    }
+
+    /// Whether the `nr`-th line is selected. The lines must be given in order.
+    /// `last` tells whether it is the last line of the input.
+    pub fn matches(&mut self, line: &str, nr: u64, last: bool) -> bool {
+        let selected = match (&self.first, &self.end) {
+            (None, _) => true,
+            (Some(first), None) => first.matches(line, nr, last),
+            (Some(_), Some(end)) if self.active => {
+                let closed = match end {
+                    End::Addr(a @ Addr::Regex(_)) | End::Addr(a @ Addr::Last) => a.matches(line, nr, last),
+                    _ => nr >= self.end_line,
+                };
+                self.active = !closed;
+                true
+            }
+            (Some(first), Some(end)) if first.matches(line, nr, last) => {
+                // The end is looked for from the next line, and the range has only one line
+                // if the end is a line number which is not bigger than the current one.
+                let end_line = match end {
+                    End::Addr(Addr::Line(n)) => *n,
+                    End::Plus(n) => nr + n,
+                    End::Multiple(0) => nr,
+                    // The next multiple, even if the current line is a multiple of N as GNU sed does
+                    End::Multiple(n) => (nr / n + 1) * n,
+                    End::Addr(_) => u64::MAX,
+                };
+                self.active = end_line > nr;
+                self.end_line = end_line;
+                true
+            }
+            _ => false,
+        };
+        selected != self.negated
+    }
+}
+
+impl Addr {
+    fn matches(&self, line: &str, nr: u64, last: bool) -> bool {
+        match self {
+            Addr::Line(n) => nr == *n,
+            Addr::Step(first, 0) => nr == *first,
+            Addr::Step(first, step) => nr >= *first && (nr - first).is_multiple_of(*step),
+            Addr::Last => last,
+            Addr::Regex(re) => re.is_match(line),
+            Addr::Zero => false,
+        }
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn skip_ws(&mut self) {
+        while self.peek().is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn number(&mut self) -> Result<u64, String> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return Err("number is expected".to_string());
+        }
+        let digits: String = self.chars[start..self.pos].iter().collect();
+        digits.parse().map_err(|_| format!("too big number {}", digits))
+    }
+
+    /// Number after the current character (+N, ~N)
+    fn next_number(&mut self) -> Result<u64, String> {
+        self.pos += 1;
+        self.number()
+    }
+
+    fn addr<F>(&mut self, regex: &F) -> Result<Addr, String>
+    where
+        F: Fn(&str) -> Result<Regex, String>,
+    {
+        match self.peek() {
+            Some('$') => {
+                self.pos += 1;
+                Ok(Addr::Last)
+            }
+            Some('/') => {
+                self.pos += 1;
+                self.regex('/', regex)
+            }
+            Some('\\') => {
+                self.pos += 1;
+                match self.peek() {
+                    Some(delim) if delim != '\n' && delim != '\\' => {
+                        self.pos += 1;
+                        self.regex(delim, regex)
+                    }
+                    _ => Err("unexpected `\\'".to_string()),
+                }
+            }
+            Some(c) if c.is_ascii_digit() => {
+                let n = self.number()?;
+                if self.eat('~') {
+                    Ok(Addr::Step(n, self.number()?))
+                } else if n == 0 {
+                    Ok(Addr::Zero)
+                } else {
+                    Ok(Addr::Line(n))
+                }
+            }
+            Some(c) => Err(format!("unexpected `{}'", c)),
+            None => Err("unexpected end of the address".to_string()),
+        }
+    }
+
+    /// Regular expression which ends with `delim` and flags (I, M) which follow it
+    fn regex<F>(&mut self, delim: char, regex: &F) -> Result<Addr, String>
+    where
+        F: Fn(&str) -> Result<Regex, String>,
+    {
+        let mut bre = String::new();
+        loop {
+            match self.peek() {
+                None => return Err("unterminated address regex".to_string()),
+                Some(c) if c == delim => break,
+                Some('\\') => {
+                    self.pos += 1;
+                    match self.peek() {
+                        // \/ in /re/ is just a slash
+                        Some(c) if c == delim => bre.push(c),
+                        Some(c) => {
+                            bre.push('\\');
+                            bre.push(c);
+                        }
+                        None => return Err("unterminated address regex".to_string()),
+                    }
+                }
+                Some(c) => bre.push(c),
+            }
+            self.pos += 1;
+        }
+        self.pos += 1;
+        if bre.is_empty() {
+            return Err("no previous regular expression".to_string());
+        }
+        let mut flags = String::new();
+        loop {
+            match self.peek() {
+                Some('I') => flags.push('i'),
+                Some('M') => flags.push('m'),
+                _ => break,
+            }
+            self.pos += 1;
+        }
+        let mut pattern = to_regex(&bre)?;
+        if !flags.is_empty() {
+            pattern = format!("(?{}){}", flags, pattern);
+        }
+        regex(&pattern).map(Addr::Regex)
+    }
+}
+
+/// Translate the basic regular expression of sed to the syntax of the regex crate.
+///   "a\(b\|c\)*" => "a(b|c)*", "1+(2)" => "1\+\(2\)"
+fn to_regex(bre: &str) -> Result<String, String> {
+    let chars: Vec<char> = bre.chars().collect();
+    let mut re = String::new();
+    // Whether the current position is at the start of the expression or a group,
+    // where `*` is a literal and `^` is an anchor
+    let mut head = true;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let was_head = head;
+        head = false;
+        match c {
+            '\\' => {
+                i += 1;
+                let e = *chars.get(i).ok_or("trailing backslash")?;
+                match e {
+                    '(' | '|' => {
+                        re.push(e);
+                        head = true;
+                    }
+                    ')' | '{' | '}' | '+' | '?' => re.push(e),
+                    '<' => re.push_str(r"\b{start}"),
+                    '>' => re.push_str(r"\b{end}"),
+                    '`' => re.push_str(r"\A"),
+                    '\'' => re.push_str(r"\z"),
+                    'b' | 'B' | 'w' | 'W' | 's' | 'S' | 'n' | 't' => {
+                        re.push('\\');
+                        re.push(e);
+                    }
+                    '1'..='9' => return Err("back-references are not supported".to_string()),
+                    _ => re.push_str(&regex::escape(&e.to_string())),
+                }
+            }
+            '*' if was_head => re.push_str(r"\*"),
+            '^' if was_head => {
+                re.push('^');
+                head = true;
+            }
+            '$' if i + 1 == chars.len() || chars[i + 1..].starts_with(&['\\', ')']) || chars[i + 1..].starts_with(&['\\', '|']) => {
+                re.push('$')
+            }
+            '.' | '*' => re.push(c),
+            '[' => i = bracket(&chars, i, &mut re)?,
+            _ => re.push_str(&regex::escape(&c.to_string())),
+        }
+        i += 1;
+    }
+    Ok(re)
+}
+
+/// Translate the bracket expression which starts at `chars[start]` and return where it ends.
+fn bracket(chars: &[char], start: usize, re: &mut String) -> Result<usize, String> {
+    re.push('[');
+    let mut i = start + 1;
+    if chars.get(i) == Some(&'^') {
+        re.push('^');
+        i += 1;
+    }
+    // `]` right after `[` or `[^` is a literal
+    if chars.get(i) == Some(&']') {
+        re.push_str(r"\]");
+        i += 1;
+    }
+    while i < chars.len() {
+        match chars[i] {
+            ']' => {
+                re.push(']');
+                return Ok(i);
+            }
+            '[' if matches!(chars.get(i + 1), Some(':') | Some('=') | Some('.')) => {
+                let kind = chars[i + 1];
+                let end = (i + 2..chars.len().saturating_sub(1))
+                    .find(|&j| chars[j] == kind && chars[j + 1] == ']')
+                    .ok_or("unterminated character class")?;
+                let name: String = chars[i + 2..end].iter().collect();
+                match kind {
+                    ':' => re.push_str(&format!("[:{}:]", name)),
+                    _ => re.push_str(&regex::escape(&name)),
+                }
+                i = end + 1;
+            }
+            // GNU sed converts these escapes even in a bracket expression
+            '\\' if matches!(chars.get(i + 1), Some('n') | Some('t') | Some('\\')) => {
+                re.push('\\');
+                re.push(chars[i + 1]);
+                i += 1;
+            }
+            '-' => re.push('-'),
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+        i += 1;
+    }
+    Err("unterminated address regex".to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn selected(script: &str, lines: &[&str]) -> Vec<u64> {
+        let mut address = Address::parse(script, |p| Regex::new(p).map_err(|e| e.to_string())).unwrap();
+        let needs_last = address.needs_last();
+        (1..=lines.len() as u64)
+            .filter(|&nr| {
+                let last = needs_last && nr == lines.len() as u64;
+                address.matches(lines[nr as usize - 1], nr, last)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_address() {
+        let lines = ["A", "B", "C", "D", "E", "F", "G"];
+        assert_eq!(selected("", &lines), vec![1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(selected("3", &lines), vec![3]);
+        assert_eq!(selected("$", &lines), vec![7]);
+        assert_eq!(selected("3!", &lines), vec![1, 2, 4, 5, 6, 7]);
+        assert_eq!(selected(" $ ! ", &lines), vec![1, 2, 3, 4, 5, 6]);
+        assert_eq!(selected("/[BD]/", &lines), vec![2, 4]);
+        assert_eq!(selected(r"\%C%", &lines), vec![3]);
+        assert_eq!(selected("/c/I", &lines), vec![3]);
+        assert_eq!(selected("1~3", &lines), vec![1, 4, 7]);
+        assert_eq!(selected("0~3", &lines), vec![3, 6]);
+        assert_eq!(selected("2~0", &lines), vec![2]);
+        assert_eq!(selected("/B/,/E/", &lines), vec![2, 3, 4, 5]);
+        assert_eq!(selected("/B/,/E/!", &lines), vec![1, 6, 7]);
+        assert_eq!(selected("2,4", &lines), vec![2, 3, 4]);
+        assert_eq!(selected("4,2", &lines), vec![4]);
+        assert_eq!(selected("5,$", &lines), vec![5, 6, 7]);
+        assert_eq!(selected("/F/,+3", &lines), vec![6, 7]);
+        assert_eq!(selected("/B/,+1", &lines), vec![2, 3]);
+        assert_eq!(selected("2,~4", &lines), vec![2, 3, 4]);
+        assert_eq!(selected("/[AE]/,/[AE]/", &lines), vec![1, 2, 3, 4, 5]);
+        assert_eq!(selected("0,/A/", &lines), vec![1]);
+        assert_eq!(selected("1,/A/", &lines), vec![1, 2, 3, 4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn test_address_multiple() {
+        // Same as `seq 10 | sed -n '<addr>p'`
+        let lines = ["1", "2", "3", "4", "5", "6", "7", "8", "9", "10"];
+        assert_eq!(selected("2,~4", &lines), vec![2, 3, 4]);
+        assert_eq!(selected("4,~4", &lines), vec![4, 5, 6, 7, 8]);
+        assert_eq!(selected("3,~3", &lines), vec![3, 4, 5, 6]);
+        assert_eq!(selected("2,~1", &lines), vec![2, 3]);
+        assert_eq!(selected("2,~0", &lines), vec![2]);
+        assert_eq!(selected("0~4,~4", &lines), vec![4, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn test_address_restart() {
+        let lines = ["x", "a", "b", "x", "a", "x", "b"];
+        assert_eq!(selected("/a/,/b/", &lines), vec![2, 3, 5, 6, 7]);
+        assert_eq!(selected("/a/,+1", &lines), vec![2, 3, 5, 6]);
+    }
+
+    #[test]
+    fn test_address_error() {
+        let regex = |p: &str| Regex::new(p).map_err(|e| e.to_string());
+        assert!(Address::parse("/abc", regex).is_err());
+        assert!(Address::parse("0", regex).is_err());
+        assert!(Address::parse("0,5", regex).is_err());
+        assert!(Address::parse("1,2~3", regex).is_err());
+        assert!(Address::parse("!", regex).is_err());
+        assert!(Address::parse("1p", regex).is_err());
+        assert!(Address::parse("//", regex).is_err());
+        assert!(Address::parse(r"/\(a\)\1/", regex).is_err());
+    }
+
+    #[test]
+    fn test_to_regex() {
+        assert_eq!(to_regex(r"a\(b\|c\)*").unwrap(), "a(b|c)*");
+        assert_eq!(to_regex("1+(2){3}|?").unwrap(), r"1\+\(2\)\{3\}\|\?");
+        assert_eq!(to_regex(r"a\{2,\}\+").unwrap(), "a{2,}+");
+        assert_eq!(to_regex("*a*").unwrap(), r"\*a*");
+        assert_eq!(to_regex("^*a").unwrap(), r"^\*a");
+        assert_eq!(to_regex("a^b$c$").unwrap(), r"a\^b\$c$");
+        assert_eq!(to_regex(r"\(^a$\)").unwrap(), "(^a$)");
+        assert_eq!(to_regex(r"\<a\.b\>").unwrap(), r"\b{start}a\.b\b{end}");
+        assert_eq!(to_regex(r"[]a-z[:digit:]\&]").unwrap(), r"[\]a-z[:digit:]\\\&]");
+        assert_eq!(to_regex(r"[^.]").unwrap(), r"[^\.]");
+        assert!(to_regex("[abc").is_err());
+    }
+}
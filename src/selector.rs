@@ -0,0 +1,157 @@
+use super::chunk::{ChunkSendError, Emitter, Index, Tracker};
+use super::{stringutils, DEFAULT_CAP};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+
+/// Part of a record which is a hole
+#[derive(Clone, Debug, PartialEq)]
+pub struct Span {
+    pub start: usize,  // Byte offset in the record where the hole starts
+    pub end: usize,    // Byte offset in the record where the hole ends (exclusive)
+    pub target: usize, // Index of the list given by `fields`
+    pub index: Index,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize, index: Index) -> Span {
+        Span {
+            start,
+            end,
+            target: 0,
+            index,
+        }
+    }
+
+    /// Send the hole to the `target`-th transform
+    pub fn to(mut self, target: usize) -> Span {
+        self.target = target;
+        self
+    }
+}
+
+/// Decides where the holes are in each record of the input.
+///
+/// A record is usually a line. `Tape` reads the records, asks the selector for the holes in
+/// each of them and sends the rest as it is.
+///
+/// ```
+/// use teip::{Index, Selector, Span, Tape};
+///
+/// // Make the first character of each line a hole
+/// struct Initial;
+/// impl Selector for Initial {
+///     fn select(&mut self, record: &str) -> Vec<Span> {
+///         match record.chars().next() {
+///             Some(c) => vec![Span::new(0, c.len_utf8(), Index::None)],
+///             None => vec![],
+///         }
+///     }
+/// }
+///
+/// let mut tape = Tape::new(Box::new(Initial), b'\n', false);
+/// let mut out = Vec::new();
+/// tape.transform(&b"foo\nbar\n"[..], &mut out, |hole, _| hole.to_uppercase()).unwrap();
+/// assert_eq!(out, b"Foo\nBar\n");
+/// ```
+pub trait Selector {
+    /// Holes in `record`, which does not include the line end.
+    /// Spans must be sorted and must not overlap. An empty span is an empty hole.
+    /// A span may extend over the line end which follows `record` (--csv).
+    fn select(&mut self, record: &str) -> Vec<Span>;
+
+    /// Called before reading `input`. A selector which reads the input by itself (-e) returns
+    /// another reader. State left by the previous input should be reset here.
+    fn open(&mut self, input: Box<dyn Read + Send>, _line_end: u8) -> io::Result<Box<dyn Read + Send>> {
+        Ok(input)
+    }
+
+    /// Same as `open` but the input is a file, which the selector may read again by itself.
+    fn open_file(&mut self, file: File, line_end: u8) -> io::Result<Box<dyn Read + Send>> {
+        self.open(Box::new(file), line_end)
+    }
+
+    /// Read a record into `buf` including the line end. Returns 0 at the end of the input.
+    /// A record may consist of multiple lines (--csv).
+    /// A selector which reads lines ahead may return `io::ErrorKind::WouldBlock` to be called again,
+    /// so that the chunks so far are flushed before reading more if the input is idle.
+    fn read_record(&mut self, input: &mut dyn BufRead, line_end: u8, buf: &mut Vec<u8>) -> io::Result<usize> {
+        input.read_until(line_end, buf)
+    }
+}
+
+/// Read `input` record by record and send the chunks to `ch`, telling the locations of the holes by `tracker`.
+/// `input` must be the one returned by `Selector::open`.
+/// Unless `solid` is true, a hole is divided at the line ends in it.
+/// The end of `input` is not told to `ch` since another input may follow.
+/// An error while reading `input` stops it and is returned as `ChunkSendError::Input`.
+pub fn drive(
+    selector: &mut dyn Selector,
+    input: Box<dyn Read + Send>,
+    ch: &mut dyn Emitter,
+    tracker: &mut Tracker,
+    line_end: u8,
+    solid: bool,
+) -> Result<(), ChunkSendError> {
+    let mut input = BufReader::new(input);
+    loop {
+        if input.buffer().is_empty() {
+            // Reading more may block (e.g. tail -f)
+            ch.send_flush()?;
+        }
+        let mut buf = Vec::with_capacity(DEFAULT_CAP);
+        match selector.read_record(&mut input, line_end, &mut buf) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+            Err(e) => return Err(ChunkSendError::Input(e)),
+        };
+        let eol = stringutils::trim_eol(&mut buf);
+        let mut record = String::from_utf8_lossy(&buf).to_string();
+        let len = record.len();
+        record.push_str(&eol);
+        let mut left_index = 0;
+        for span in selector.select(&record[..len]) {
+            if left_index < span.start {
+                send_keep(ch, tracker, &record[left_index..span.start])?;
+            }
+            send_hole(ch, tracker, &record[span.start..span.end], &span, line_end, solid)?;
+            left_index = span.end;
+        }
+        if left_index < len {
+            send_keep(ch, tracker, &record[left_index..len])?;
+        }
+        // Line end, unless a hole covers it
+        send_keep(ch, tracker, &record[left_index.max(len)..])?;
+    }
+    Ok(())
+}
+
+fn send_keep(ch: &mut dyn Emitter, tracker: &mut Tracker, msg: &str) -> Result<(), ChunkSendError> {
+    tracker.keep(msg);
+    ch.send_keep(msg.to_string())
+}
+
+fn send_hole(
+    ch: &mut dyn Emitter,
+    tracker: &mut Tracker,
+    hole: &str,
+    span: &Span,
+    line_end: u8,
+    solid: bool,
+) -> Result<(), ChunkSendError> {
+    let line_end = line_end as char;
+    if solid || !hole.contains(line_end) {
+        let loc = tracker.hole(hole, span.index);
+        return ch.send_byps_to(span.target, hole.to_string(), loc);
+    }
+    for (i, part) in hole.split(line_end).enumerate() {
+        if i > 0 {
+            send_keep(ch, tracker, &line_end.to_string())?;
+        }
+        if !part.is_empty() {
+            let loc = tracker.hole(part, span.index);
+            ch.send_byps_to(span.target, part.to_string(), loc)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,445 @@
+use super::errors::*;
+use super::{builtin, script, spawnutils, wasm, DEFAULT_CAP, HL, HL_ALT};
+use teip::Location;
+
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::Child;
+use std::time::Duration;
+use log::debug;
+
+/// How long to wait for the targeted command to exit once its output is exhausted
+const EXHAUSTED_WAIT: Duration = Duration::from_millis(500);
+
+/// Function which transforms the string in the hole in-process (--builtin, --script, --wasm).
+/// It receives the string and its location, and returns the string to be printed instead.
+pub type Transform = Box<dyn FnMut(&str, &Location) -> Result<String, String> + Send + 'static>;
+
+/// Backend which gives the strings printed instead of the holes
+///
+/// `PipeIntercepter` calls `fill` from its output thread in the order of the holes.
+pub trait HoleSink: Send {
+    /// Writers which receive the strings in the holes while the input is being read,
+    /// one for each target. Each string is followed by the line end.
+    /// If they are given, `fill` receives an empty string and reads the result by itself.
+    fn inputs(&mut self) -> Vec<Box<dyn Write + Send>> {
+        Vec::new()
+    }
+
+    /// Whether `fill` needs all of the holes in advance (--collect).
+    /// If true, the output thread keeps the chunks until the end of the input and calls `prepare`.
+    fn gathers(&self) -> bool {
+        false
+    }
+
+    /// Receive all of the holes as `(target, string)` before `fill` if `gathers` is true.
+    fn prepare(&mut self, _holes: Vec<(usize, &str)>) -> Result<(), SinkError> {
+        Ok(())
+    }
+
+    /// The string printed instead of the hole for the `target`-th targeted command.
+    fn fill(&mut self, target: usize, msg: String, loc: &Location) -> Result<String, SinkError>;
+
+    /// Called after the last hole.
+    /// Returns the targeted commands which did not finish successfully.
+    fn finish(&mut self) -> Vec<ChildFailure> {
+        Vec::new()
+    }
+}
+
+/// Read a line from the pipe without its line end
+fn read_pipe<R: BufRead + ?Sized>(reader: &mut R, line_end: u8) -> Result<String, PipeReceiveError> {
+    debug!("thread: read_pipe");
+    let mut buf = Vec::with_capacity(DEFAULT_CAP);
+    let n = reader
+        .read_until(line_end, &mut buf)
+        .map_err(PipeReceiveError::Io)?;
+    if n == 0 {
+        // If pipe is exhausted, throw error.
+        return Err(PipeReceiveError::EndOfFd);
+    }
+    // Remove line_end from buf. "\r\n" is also removed, which a terminal may print (--pty).
+    if buf.ends_with(b"\r\n") {
+        buf.truncate(buf.len() - 2);
+    } else if buf.ends_with(b"\n") || buf.ends_with(b"\0") {
+        buf.pop();
+    }
+    Ok(String::from_utf8_lossy(&buf).to_string())
+}
+
+/// Stream the holes to the targeted commands which keep running (default)
+pub struct PipeSink {
+    pipe_writers: Vec<Box<dyn Write + Send>>,
+    pipe_readers: Vec<BufReader<Box<dyn Read + Send>>>,
+    children: Vec<Option<Child>>,
+    cmd_names: Vec<String>,
+    line_end: u8,
+    ignore_status: bool,
+}
+
+impl PipeSink {
+    /// If `pty` is true, the commands print to a pseudo-terminal (--pty).
+    pub fn new(cmds: &[Vec<String>], line_end: u8, ignore_status: bool, pty: bool) -> Result<PipeSink, SpawnError> {
+        let mut sink = PipeSink {
+            pipe_writers: Vec::new(),
+            pipe_readers: Vec::new(),
+            children: Vec::new(),
+            cmd_names: Vec::new(),
+            line_end,
+            ignore_status,
+        };
+        for c in cmds.iter() {
+            let (child_stdin, child_stdout, child) = if pty {
+                spawnutils::exec_cmd_pty(c)?
+            } else {
+                spawnutils::exec_cmd(c)?
+            };
+            // The output of each command is read on its own thread if there are multiple targets
+            let child_stdout = if cmds.len() > 1 { spawnutils::drain(child_stdout) } else { child_stdout };
+            sink.pipe_writers.push(child_stdin);
+            sink.pipe_readers.push(BufReader::new(child_stdout));
+            sink.children.push(child);
+            sink.cmd_names.push(c.first().cloned().unwrap_or_default());
+        }
+        Ok(sink)
+    }
+}
+
+impl HoleSink for PipeSink {
+    fn inputs(&mut self) -> Vec<Box<dyn Write + Send>> {
+        std::mem::take(&mut self.pipe_writers)
+    }
+
+    fn fill(&mut self, target: usize, _msg: String, _loc: &Location) -> Result<String, SinkError> {
+        read_pipe(&mut self.pipe_readers[target], self.line_end).map_err(|e| {
+            // pipe may be exhausted
+            // The command may have crashed. If so, report it rather than the pipe.
+            let failure = self.children[target]
+                .as_mut()
+                .and_then(|c| spawnutils::wait_timeout(c, EXHAUSTED_WAIT))
+                .and_then(|status| ChildFailure::check(&self.cmd_names[target], None, status))
+                .filter(|_| !self.ignore_status);
+            SinkError::Command(e.to_string(), failure)
+        })
+    }
+
+    fn finish(&mut self) -> Vec<ChildFailure> {
+        // Wait until the pipe_writers are closed and the commands exit.
+        let mut failures = Vec::new();
+        for (child, name) in self.children.iter_mut().zip(self.cmd_names.iter()) {
+            if let Some(c) = child {
+                match c.wait() {
+                    Ok(status) => failures.extend(ChildFailure::check(name, None, status)),
+                    Err(e) => msg_error(&e.to_string()),
+                }
+            }
+        }
+        failures
+    }
+}
+
+/// Execute the targeted command for each hole (-s, -I)
+pub struct SpawnSink {
+    cmds: Vec<Vec<String>>,
+    line_end: u8,
+    chomp: bool,
+    replace_str: Option<String>,
+    shell: Option<String>, // The holes are quoted for the shell (-S)
+    failures: Vec<ChildFailure>,
+}
+
+impl SpawnSink {
+    pub fn new(cmds: Vec<Vec<String>>, line_end: u8, chomp: bool, replace_str: Option<String>, shell: Option<String>) -> SpawnSink {
+        SpawnSink {
+            cmds,
+            line_end,
+            chomp,
+            replace_str,
+            shell,
+            failures: Vec::new(),
+        }
+    }
+}
+
+impl HoleSink for SpawnSink {
+    fn fill(&mut self, target: usize, msg: String, loc: &Location) -> Result<String, SinkError> {
+        let cmds = &self.cmds[target];
+        // -I option
+        let (result, status) = match self.replace_str {
+            Some(ref replace_str) => {
+                let msg = match self.shell {
+                    Some(ref shell) => spawnutils::shell_quote(shell, &msg),
+                    None => msg,
+                };
+                spawnutils::exec_cmd_sync_replace(msg, cmds, self.line_end, self.chomp, replace_str, loc)
+            }
+            None => spawnutils::exec_cmd_sync(msg, cmds, self.line_end, self.chomp, loc),
+        };
+        self.failures.extend(ChildFailure::check(&cmds[0], Some(loc.seq), status));
+        Ok(result)
+    }
+
+    fn finish(&mut self) -> Vec<ChildFailure> {
+        std::mem::take(&mut self.failures)
+    }
+}
+
+/// Execute the targeted command once over all of the holes, and put the results back in order
+/// (--collect). The command must print a line for each hole.
+pub struct CollectSink {
+    cmds: Vec<Vec<String>>,
+    line_end: u8,
+    ignore_status: bool,
+    results: Vec<VecDeque<String>>, // One for each targeted command
+    failures: Vec<ChildFailure>,
+}
+
+impl CollectSink {
+    pub fn new(cmds: Vec<Vec<String>>, line_end: u8, ignore_status: bool) -> CollectSink {
+        CollectSink {
+            cmds,
+            line_end,
+            ignore_status,
+            results: Vec::new(),
+            failures: Vec::new(),
+        }
+    }
+
+    /// Give `input` to the command and return the lines it prints
+    fn run(&mut self, target: usize, input: Vec<u8>) -> Result<VecDeque<String>, SinkError> {
+        let cmds = &self.cmds[target];
+        let spawn_err = |e: SpawnError| SinkError::Command(e.to_string(), None);
+        let (mut child_stdin, child_stdout, child) = spawnutils::exec_cmd(cmds).map_err(spawn_err)?;
+        // Write in another thread not to be blocked by the command which prints before reading all.
+        let writer = std::thread::spawn(move || {
+            // The command may exit without reading standard input.
+            // Such a failure is reported with its exit status.
+            let _ = child_stdin.write_all(&input);
+        });
+        let mut reader = BufReader::new(child_stdout);
+        let mut lines = VecDeque::new();
+        while let Ok(line) = read_pipe(&mut reader, self.line_end) {
+            lines.push_back(line);
+        }
+        let _ = writer.join();
+        if let Some(mut c) = child {
+            match c.wait() {
+                Ok(status) => self.failures.extend(ChildFailure::check(&cmds[0], None, status)),
+                Err(e) => msg_error(&e.to_string()),
+            }
+        }
+        Ok(lines)
+    }
+}
+
+impl HoleSink for CollectSink {
+    fn gathers(&self) -> bool {
+        true
+    }
+
+    fn prepare(&mut self, holes: Vec<(usize, &str)>) -> Result<(), SinkError> {
+        for target in 0..self.cmds.len() {
+            let mut input = Vec::new();
+            let mut count = 0;
+            for (_, msg) in holes.iter().filter(|(t, _)| *t == target) {
+                input.extend_from_slice(msg.as_bytes());
+                input.push(self.line_end);
+                count += 1;
+            }
+            if count == 0 {
+                self.results.push(VecDeque::new());
+                continue;
+            }
+            let failures = self.failures.len();
+            let lines = self.run(target, input)?;
+            if lines.len() != count {
+                // The command may have crashed. If so, report it as well.
+                let ignore_status = self.ignore_status;
+                let failure = self.failures.drain(failures..).next().filter(|_| !ignore_status);
+                let msg = format!(
+                    "--collect: {} printed {} lines for {} holes",
+                    self.cmds[target][0],
+                    lines.len(),
+                    count
+                );
+                return Err(SinkError::Command(msg, failure));
+            }
+            self.results.push(lines);
+        }
+        Ok(())
+    }
+
+    fn fill(&mut self, target: usize, _msg: String, _loc: &Location) -> Result<String, SinkError> {
+        self.results[target]
+            .pop_front()
+            .ok_or_else(|| SinkError::Hole("--collect: No result for the hole".to_string()))
+    }
+
+    fn finish(&mut self) -> Vec<ChildFailure> {
+        std::mem::take(&mut self.failures)
+    }
+}
+
+/// Highlight the holes instead of transforming them (no <command>)
+#[derive(Clone, Copy)]
+pub struct HighlightSink {
+    color: bool, // Use TEIP_HIGHLIGHT and TEIP_HIGHLIGHT_ALT rather than plain brackets
+    index: bool, // Prefix the holes with their numbers (--hole-index)
+}
+
+impl HighlightSink {
+    pub fn new(color: bool, index: bool) -> HighlightSink {
+        HighlightSink { color, index }
+    }
+}
+
+impl HoleSink for HighlightSink {
+    fn fill(&mut self, _target: usize, msg: String, loc: &Location) -> Result<String, SinkError> {
+        let hl: &[&str] = match (self.color, loc.seq % 2) {
+            (false, _) => &["[", "]"],
+            (true, 0) => &HL_ALT,
+            (true, _) => &HL,
+        };
+        match self.index {
+            true => Ok(format!("{}{}:{}{}", hl[0], loc.seq, msg, hl[1])),
+            false => Ok(hl[0].to_string() + msg.as_str() + hl[1]),
+        }
+    }
+}
+
+/// Transform the holes in-process (--builtin, --script, --wasm)
+pub struct TransformSink(pub Transform);
+
+impl HoleSink for TransformSink {
+    fn fill(&mut self, _target: usize, msg: String, loc: &Location) -> Result<String, SinkError> {
+        (self.0)(&msg, loc).map_err(SinkError::Hole)
+    }
+}
+
+/// Send each hole to the server listening on the Unix domain socket, and read a line as the result
+/// (--sink socket:<path>)
+#[cfg(unix)]
+pub struct SocketSink {
+    path: String,
+    reader: BufReader<std::os::unix::net::UnixStream>,
+    writer: std::os::unix::net::UnixStream,
+    line_end: u8,
+}
+
+#[cfg(unix)]
+impl SocketSink {
+    pub fn new(path: &str, line_end: u8) -> Result<SocketSink, String> {
+        let writer = std::os::unix::net::UnixStream::connect(path).map_err(|e| format!("{}: {}", path, e))?;
+        let reader = BufReader::new(writer.try_clone().map_err(|e| format!("{}: {}", path, e))?);
+        Ok(SocketSink {
+            path: path.to_string(),
+            reader,
+            writer,
+            line_end,
+        })
+    }
+}
+
+#[cfg(unix)]
+impl HoleSink for SocketSink {
+    fn fill(&mut self, _target: usize, mut msg: String, _loc: &Location) -> Result<String, SinkError> {
+        let path = &self.path;
+        let err = |e: &dyn std::fmt::Display| SinkError::Hole(format!("{}: {}", path, e));
+        msg.push(self.line_end as char);
+        self.writer.write_all(msg.as_bytes()).map_err(|e| err(&e))?;
+        read_pipe(&mut self.reader, self.line_end).map_err(|e| err(&e))
+    }
+}
+
+/// --builtin <name>[:<args>]
+pub fn builtin(spec: &str) -> Result<Box<dyn HoleSink>, String> {
+    let mut b = builtin::Builtin::parse(spec)?;
+    Ok(Box::new(TransformSink(Box::new(move |s, _| b.apply(s)))))
+}
+
+/// --script <script>
+pub fn script(src: &str) -> Result<Box<dyn HoleSink>, String> {
+    let mut sc = script::Script::new(src)?;
+    Ok(Box::new(TransformSink(Box::new(move |s, loc| sc.apply(s, loc)))))
+}
+
+/// --wasm <file>
+pub fn wasm(path: &str) -> Result<Box<dyn HoleSink>, String> {
+    let mut p = wasm::Plugin::new(path)?;
+    Ok(Box::new(TransformSink(Box::new(move |s, _| p.apply(s)))))
+}
+
+/// --sink socket:<path>
+pub fn socket(path: &str, line_end: u8) -> Result<Box<dyn HoleSink>, String> {
+    cfg_if::cfg_if! {
+        if #[cfg(unix)] {
+            Ok(Box::new(SocketSink::new(path, line_end)?))
+        } else {
+            let _ = (path, line_end);
+            Err("socket sink is not supported on this platform".to_string())
+        }
+    }
+}
+
+/// Parse the argument of --sink
+///   "builtin:upper"        => Same as --builtin upper
+///   "script:<file>"        => Same as --script-file <file>
+///   "wasm:<file>"          => Same as --wasm <file>
+///   "socket:/tmp/teip.sock" => Send the holes to the Unix domain socket
+pub fn parse(spec: &str, line_end: u8) -> Result<Box<dyn HoleSink>, String> {
+    let (kind, arg) = spec.split_once(':').unwrap_or((spec, ""));
+    if arg.is_empty() {
+        return Err(format!("--sink: argument is required: {}:<argument>", kind));
+    }
+    match kind {
+        "builtin" => builtin(arg),
+        "script" => {
+            let src = std::fs::read_to_string(arg).map_err(|e| format!("{}: {}", arg, e))?;
+            script(&src)
+        }
+        "wasm" => wasm(arg),
+        "socket" => socket(arg, line_end),
+        _ => Err(format!(
+            "--sink: Unknown sink '{}'. Use builtin:<name>, script:<file>, wasm:<file> or socket:<path>",
+            kind
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use teip::Index;
+
+    fn loc() -> Location {
+        Location { seq: 1, line: 1, byte_offset: 0, index: Index::None }
+    }
+
+    #[test]
+    fn test_parse() {
+        let mut s = parse("builtin:upper", b'\n').ok().unwrap();
+        assert_eq!(s.fill(0, "abc".to_string(), &loc()).ok(), Some("ABC".to_string()));
+        assert!(s.inputs().is_empty());
+        assert!(parse("builtin", b'\n').is_err());
+        assert!(parse("unknown:x", b'\n').is_err());
+        assert!(parse("builtin:unknown", b'\n').is_err());
+    }
+
+    #[test]
+    fn test_transform_error() {
+        let mut s = TransformSink(Box::new(|_, _| Err("bad".to_string())));
+        match s.fill(0, "abc".to_string(), &loc()) {
+            Err(SinkError::Hole(e)) => assert_eq!(e, "bad"),
+            _ => panic!("error is expected"),
+        }
+    }
+
+    #[test]
+    fn test_highlight_plain() {
+        let mut s = HighlightSink::new(false, false);
+        assert_eq!(s.fill(0, "abc".to_string(), &loc()).ok(), Some("[abc]".to_string()));
+        let mut s = HighlightSink::new(false, true);
+        let second = Location { seq: 2, ..loc() };
+        assert_eq!(s.fill(0, "abc".to_string(), &second).ok(), Some("[2:abc]".to_string()));
+    }
+}
@@ -1,67 +1,268 @@
-use super::DEFAULT_CAP;
 use super::errors;
-use super::stringutils;
-use std::thread::JoinHandle;
-use std::io::{self, BufRead, BufWriter, BufReader, Read, Write};
+use teip::{Index, Location};
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{self, Receiver};
 use std::thread;
-use std::process::{Command, Stdio};
-use std::sync::mpsc::{self,Receiver};
+use std::time::{Duration, Instant};
+use std::process::{Child, Command, ExitStatus, Stdio};
 use log::debug;
 
 /// Execute command and return two pipes, stdin and stdout of the new process.
+/// The process itself is also returned so that the caller can wait for it.
 pub fn exec_cmd(
     cmds: &Vec<String>,
 ) -> std::result::Result<
     (
         Box<dyn Write + Send + 'static>,
         Box<dyn Read + Send + 'static>,
-        String,
+        Option<Child>,
     ),
     errors::SpawnError,
 > {
     debug!("thread: exec_cmd: {:?}", cmds);
     if cmds.len() == 0 {
         // In the case of dryrun, return dummy objects.
-        return Ok((Box::new(io::sink()), Box::new(io::empty()), "".to_string()));
+        return Ok((Box::new(io::sink()), Box::new(io::empty()), None));
     }
-    let child = Command::new(&cmds[0])
+    let mut child = Command::new(&cmds[0])
         .args(&cmds[1..])
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
         .spawn()
         .map_err(|e| errors::SpawnError::Io(e))?;
-    let first = &cmds[0];
-    let child_stdin = child.stdin.ok_or(errors::SpawnError::StdinOpenFailed)?;
-    let child_stdout = child.stdout.ok_or(errors::SpawnError::StdoutOpenFailed)?;
+    let child_stdin = child.stdin.take().ok_or(errors::SpawnError::StdinOpenFailed)?;
+    let child_stdout = child.stdout.take().ok_or(errors::SpawnError::StdoutOpenFailed)?;
     Ok((
         Box::new(child_stdin),
         Box::new(child_stdout),
-        first.to_string(),
+        Some(child),
+    ))
+}
+
+/// Same as `exec_cmd` but the standard output of the new process is a pseudo-terminal (--pty).
+/// Most commands flush their output line by line when it is a terminal.
+/// The standard input is still a pipe so that the command can see the end of it.
+#[cfg(unix)]
+pub fn exec_cmd_pty(
+    cmds: &Vec<String>,
+) -> std::result::Result<
+    (
+        Box<dyn Write + Send + 'static>,
+        Box<dyn Read + Send + 'static>,
+        Option<Child>,
+    ),
+    errors::SpawnError,
+> {
+    debug!("thread: exec_cmd_pty: {:?}", cmds);
+    if cmds.is_empty() {
+        return Ok((Box::new(io::sink()), Box::new(io::empty()), None));
+    }
+    let (master, slave) = pty::open().map_err(errors::SpawnError::Io)?;
+    let mut child = Command::new(&cmds[0])
+        .args(&cmds[1..])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::from(slave))
+        .spawn()
+        .map_err(errors::SpawnError::Io)?;
+    let child_stdin = child.stdin.take().ok_or(errors::SpawnError::StdinOpenFailed)?;
+    Ok((
+        Box::new(child_stdin),
+        Box::new(pty::Reader(master)),
+        Some(child),
     ))
 }
 
+#[cfg(not(unix))]
+pub fn exec_cmd_pty(
+    _cmds: &Vec<String>,
+) -> std::result::Result<
+    (
+        Box<dyn Write + Send + 'static>,
+        Box<dyn Read + Send + 'static>,
+        Option<Child>,
+    ),
+    errors::SpawnError,
+> {
+    Err(errors::SpawnError::Io(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "--pty is not supported on this platform",
+    )))
+}
+
+#[cfg(unix)]
+mod pty {
+    use std::fs::File;
+    use std::io::{self, Read};
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+    use std::ptr;
+
+    /// Open a pseudo-terminal and return its master and slave.
+    pub fn open() -> io::Result<(File, File)> {
+        let mut master = 0;
+        let mut slave = 0;
+        let ret = unsafe { libc::openpty(&mut master, &mut slave, ptr::null_mut(), ptr::null(), ptr::null()) };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let (master, slave) = unsafe { (File::from_raw_fd(master), File::from_raw_fd(slave)) };
+        unsafe {
+            // The master must not be inherited by the command, otherwise the output never ends.
+            libc::fcntl(master.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC);
+            // Do not translate "\n" into "\r\n"
+            let mut term: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(slave.as_raw_fd(), &mut term) == 0 {
+                term.c_oflag &= !libc::OPOST;
+                libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &term);
+            }
+        }
+        Ok((master, slave))
+    }
+
+    /// Read the output from the master.
+    /// Reading it fails with EIO once the command closes the slave, which is the end of the output.
+    pub struct Reader(pub File);
+
+    impl Read for Reader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.read(buf) {
+                Err(e) if e.raw_os_error() == Some(libc::EIO) => Ok(0),
+                r => r,
+            }
+        }
+    }
+}
+
+/// Wait for the process to exit at most `timeout`.
+/// Returns None if the process is still running.
+pub fn wait_timeout(child: &mut Child, timeout: Duration) -> Option<ExitStatus> {
+    let start = Instant::now();
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => return Some(status),
+            Ok(None) if start.elapsed() < timeout => thread::sleep(Duration::from_millis(10)),
+            _ => return None,
+        }
+    }
+}
+
+/// Read the output of the command on another thread as soon as it is printed.
+/// The output thread waits for one of the targeted commands at a time,
+/// so the others must not block on their full pipes meanwhile (`cmdA ::: cmdB`).
+pub fn drain(mut reader: Box<dyn Read + Send>) -> Box<dyn Read + Send> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => {
+                    if tx.send(Ok(buf[..n].to_vec())).is_err() {
+                        break;
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    let _ = tx.send(Err(e));
+                    break;
+                }
+            }
+        }
+    });
+    Box::new(Drained { rx, buf: Vec::new(), pos: 0 })
+}
+
+/// The output read by `drain`
+struct Drained {
+    rx: Receiver<io::Result<Vec<u8>>>,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for Drained {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.buf.len() {
+            match self.rx.recv() {
+                Ok(buf) => {
+                    self.buf = buf?;
+                    self.pos = 0;
+                }
+                Err(_) => return Ok(0),
+            }
+        }
+        let n = (&self.buf[self.pos..]).read(out)?;
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Environment variables which tell the command where the hole is located
+const LOCATION_ENVS: [&str; 5] = [
+    "TEIP_HOLE_SEQ",
+    "TEIP_LINE_NUMBER",
+    "TEIP_BYTE_OFFSET",
+    "TEIP_FIELD_INDEX",
+    "TEIP_MATCH_INDEX",
+];
+
+/// Set environment variables of the location of the hole.
+/// Variables which are not applicable are removed so as not to inherit them from nested teip.
+fn set_location_envs(cmd: &mut Command, loc: &Location) {
+    for key in LOCATION_ENVS.iter() {
+        cmd.env_remove(key);
+    }
+    cmd.env("TEIP_HOLE_SEQ", loc.seq.to_string())
+        .env("TEIP_LINE_NUMBER", loc.line.to_string())
+        .env("TEIP_BYTE_OFFSET", loc.byte_offset.to_string());
+    match loc.index {
+        Index::Field(n) => { cmd.env("TEIP_FIELD_INDEX", n.to_string()); },
+        Index::Match(n) => { cmd.env("TEIP_MATCH_INDEX", n.to_string()); },
+        Index::None => {},
+    }
+}
+
+/// Replace placeholders of the location like `{nr}` with actual values (-I option)
+fn expand_location(arg: &str, loc: &Location) -> String {
+    let (field, matched) = match loc.index {
+        Index::Field(n) => (n.to_string(), "".to_string()),
+        Index::Match(n) => ("".to_string(), n.to_string()),
+        Index::None => ("".to_string(), "".to_string()),
+    };
+    arg.replace("{nr}", &loc.line.to_string())
+        .replace("{field}", &field)
+        .replace("{match}", &matched)
+        .replace("{seq}", &loc.seq.to_string())
+        .replace("{offset}", &loc.byte_offset.to_string())
+}
+
+/// Replace <replace-str> with the hole and placeholders with the location of the hole.
+/// The hole itself is never expanded even if it contains placeholders.
+fn replace_arg(arg: &str, replace_str: &str, input: &str, loc: &Location) -> String {
+    arg.split(replace_str)
+        .map(|s| expand_location(s, loc))
+        .collect::<Vec<String>>()
+        .join(input)
+}
+
 /// Execute single command and return the stdout of the command as String synchronously with
-/// placeholder replacement (-I option)
-pub fn exec_cmd_sync_replace(input: String, cmds: &Vec<String>, line_end: u8, chomp: bool, replace_str: &str) -> String {
+/// placeholder replacement (-I option). The exit status of the command is returned as well.
+pub fn exec_cmd_sync_replace(input: String, cmds: &Vec<String>, line_end: u8, chomp: bool, replace_str: &str, loc: &Location) -> (String, ExitStatus) {
     debug!("thread: exec_cmd_sync: {:?}", &cmds);
     // check each element of cmds. If it contains replace_str, replace it with input
-    let mut cmds_new = Vec::new();
-    for cmd in cmds {
-        if cmd.contains(replace_str) {
-            cmds_new.push(cmd.replace(replace_str, &input));
-        } else {
-            cmds_new.push(cmd.to_string());
-        }
-    }
-    let child = Command::new(&cmds_new[0])
+    let cmds_new: Vec<String> = cmds
+        .iter()
+        .map(|cmd| replace_arg(cmd, replace_str, &input, loc))
+        .collect();
+    let mut command = Command::new(&cmds_new[0]);
+    set_location_envs(&mut command, loc);
+    let child = command
         .args(&cmds_new[1..])
         .stdout(Stdio::piped())
         .spawn()
         .expect("Failed to spawn child process");
-    let mut output = child
+    let result = child
         .wait_with_output()
-        .expect("Failed to read stdout")
-        .stdout;
+        .expect("Failed to read stdout");
+    let mut output = result.stdout;
     if !chomp {
         // Remove training new line.
         // In the vast majority of cases,
@@ -70,15 +271,18 @@ pub fn exec_cmd_sync_replace(input: String, cmds: &Vec<String>, line_end: u8, ch
             output.pop();
         }
     }
-    String::from_utf8_lossy(&output).to_string()
+    (String::from_utf8_lossy(&output).to_string(), result.status)
 }
 
 
 
 /// Execute single command and return the stdout of the command as String synchronously
-pub fn exec_cmd_sync(input: String, cmds: &Vec<String>, line_end: u8, chomp: bool) -> String {
+/// with the exit status of the command.
+pub fn exec_cmd_sync(input: String, cmds: &Vec<String>, line_end: u8, chomp: bool, loc: &Location) -> (String, ExitStatus) {
     debug!("thread: exec_cmd_sync: {:?}", &cmds);
-    let mut child = Command::new(&cmds[0])
+    let mut command = Command::new(&cmds[0]);
+    set_location_envs(&mut command, loc);
+    let mut child = command
         .args(&cmds[1..])
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
@@ -98,14 +302,16 @@ pub fn exec_cmd_sync(input: String, cmds: &Vec<String>, line_end: u8, chomp: boo
             // ADD NEW LINE: Add trailing new lines to unify the behavior with exec_cmd
             vec.extend_from_slice(&[line_end]);
         }
-        stdin
-            .write_all(vec.as_slice())
-            .expect("Failed to write to stdin");
+        // The command may exit without reading standard input.
+        // Such a failure is reported with its exit status.
+        if let Err(e) = stdin.write_all(vec.as_slice()) {
+            debug!("thread: exec_cmd_sync: failed to write: {}", e);
+        }
     }
-    let mut output = child
+    let result = child
         .wait_with_output()
-        .expect("Failed to read stdout")
-        .stdout;
+        .expect("Failed to read stdout");
+    let mut output = result.stdout;
     if !chomp {
         // Remove training new line.
         // In the vast majority of cases,
@@ -114,207 +320,72 @@ pub fn exec_cmd_sync(input: String, cmds: &Vec<String>, line_end: u8, chomp: boo
             output.pop();
         }
     }
-    String::from_utf8_lossy(&output).to_string()
+    (String::from_utf8_lossy(&output).to_string(), result.status)
 }
 
-/// Spawn process with a single string command (pipeline) and keep getting input from mpsc::Receiver as stdin.
-pub fn exec_pipeline_mpsc_input (
-    command: &str,
-    input: Receiver<Vec<u8>>,
-    ) -> std::result::Result<
-            (BufReader<Box<dyn Read + Send>>, JoinHandle<()>),
-            errors::SpawnError
-    > {
-    cfg_if::cfg_if! {
-        if #[cfg(windows)] {
-            let cmds: Vec<String> = vec!["cmd","/C", command].into_iter().map(|s| s.to_owned()).collect();
-        } else {
-            let cmds: Vec<String> = vec!["sh","-c", command].into_iter().map(|s| s.to_owned()).collect();
-        }
-    }
-    let (fd_in, fd_out, _) = self::exec_cmd(&cmds)?;
-    let mut n_writer = BufWriter::new(fd_in);
-    let n_reader = BufReader::new(fd_out);
-    let handler = thread::spawn(move || {
-        loop {
-            let buf = match input.recv() {
-                Ok(buf) => buf,
-                Err(_) => break, // Generally, entering here means queue just got emptied. Do nothing.
-            };
-            match n_writer.write(&buf) {
-                Ok(0) => break,
-                _ => {},         // Ignore error because the command may not accept standard input (i.e seq command).
-            };
-        }
-        n_writer = BufWriter::new(Box::new(io::sink()));
-        drop(n_writer);
-    });
-    Ok((n_reader, handler))
+/// Whether the shell is cmd.exe, which takes `/C` instead of `-c` and quotes with `"`.
+fn is_cmd(shell: &str) -> bool {
+    std::path::Path::new(shell)
+        .file_stem()
+        .is_some_and(|s| s.to_string_lossy().eq_ignore_ascii_case("cmd"))
 }
 
-/// Generate two mpsc::Receiver(s) which prints identical standard input as given trait.
-/// The behavior is similar to tee(1) command but `mpsc:channel` queues data as much as they can
-/// unlike general Linux pipe buffer.
-/// `line_end` is line delimiter, generally it is supposed to be the new-line character like `\n`.
-///
-/// Example of duplicating standard input:
-/// ```
-/// let stdin = io::stdin();
-/// let (stdin1, stdin2, _thread1) = tee(stdin, b'\n').unwrap();
-/// ```
-/// => `stdin1` and `stdin2` will receive identical data as `io::stdin()`.
-pub fn tee(
-    input: (impl Read + Send + 'static),
-    line_end: u8
-) -> std::result::Result<(Receiver<Vec<u8>>, Receiver<Vec<u8>>, JoinHandle<()>), errors::SpawnError> {
-    let (tx1, rx1) = mpsc::channel();
-    let (tx2, rx2) = mpsc::channel();
-    let handler = thread::spawn(move || {
-            let mut stdin = BufReader::new(input);
-            loop {
-                let mut buf = Vec::with_capacity(DEFAULT_CAP);
-                match stdin.read_until(line_end, &mut buf) {
-                    Ok(0) => {
-                        // Finish to read entire input, discard channels
-                        drop(tx1);
-                        drop(tx2);
-                        break;
-                    },
-                    Ok(_) => {
-                        // FIXME: This part requires memory capacity more than double of stdin.
-                        // There is a room to reduce memory usage.
-                        let _ = tx1.send(buf.clone());
-                        let _ = tx2.send(buf.clone());
-                    },
-                    Err(_) => {
-                        debug!("tee_chain: Got error while loading from stdin");
-                    },
-                };
-            }
-    });
-    return Ok((rx1, rx2, handler))
+/// Quote `word` so that the shell reads it as a single word as it is.
+pub fn shell_quote(shell: &str, word: &str) -> String {
+    if is_cmd(shell) {
+        if !word.is_empty() && !word.contains(|c: char| c.is_whitespace() || "\"&|<>^%()".contains(c)) {
+            return word.to_string();
+        }
+        return format!("\"{}\"", word.replace('"', "\"\""));
+    }
+    if !word.is_empty() && word.chars().all(|c| c.is_ascii_alphanumeric() || "_-+=.,/:@%".contains(c)) {
+        return word.to_string();
+    }
+    format!("'{}'", word.replace('\'', "'\\''"))
 }
 
-/// Extract numbers from noisey strings.
-pub fn clean_numbers (
-    mut input: BufReader<Box<dyn Read + Send>>,
-    line_end: u8
-) -> (Receiver<u64>, JoinHandle<()>) {
-    let (tx, rx) = mpsc::channel();
-    let handler = thread::spawn(move || {
-        debug!("clean_numbers: thread: start");
-        loop {
-            let mut buf = Vec::with_capacity(DEFAULT_CAP);
-            match input.read_until(line_end, &mut buf) {
-                Ok(0) => break,
-                Ok(_) => {},
-                Err(_) => break,
-            };
-            let line = String::from_utf8_lossy(&buf).to_string();
-            match stringutils::extract_number(line) {
-                Some(i) => {
-                    match tx.send(i) {
-                        Ok(_) => (),
-                        Err(_) => {
-                            break;
-                        },
-                    }
-                },
-                None => {},
-            };
-        }
-        drop(tx);
-        debug!("clean_numbers: thread: end");
-    });
-    return (rx, handler)
+/// Build arguments to execute a single string command (pipeline) with the given shell.
+/// The first element of `command` is the string, and the rest are quoted and appended to it as arguments.
+pub fn shell_cmds(shell: &str, command: &[String]) -> Vec<String> {
+    let opt = if is_cmd(shell) { "/C" } else { "-c" };
+    let mut line = command.first().cloned().unwrap_or_default();
+    for arg in command.iter().skip(1) {
+        line.push(' ');
+        line.push_str(&shell_quote(shell, arg));
+    }
+    vec![shell.to_string(), opt.to_string(), line]
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
     #[test]
-    fn test_tee() {
-        let input = b"AAA\nBBB\nCCC\n";
-        let expected = [b"AAA\n", b"BBB\n", b"CCC\n"];
-        let (stdin1, stdin2, _thread1) = tee(&input[..], b'\n').unwrap();
-        for e in expected {
-            match stdin1.recv() {
-                Ok(s) => {
-                    assert_eq!(s, e);
-                },
-                Err(_) => {
-                    assert!(false);
-                },
-            }
-            match stdin2.recv() {
-                Ok(s) => {
-                    assert_eq!(s, e);
-                },
-                Err(_) => {
-                    assert!(false);
-                },
-            }
-        }
+    fn test_replace_arg() {
+        let loc = Location { seq: 2, line: 5, byte_offset: 30, index: Index::Field(3) };
+        assert_eq!(replace_arg("{nr}:{field}:{}", "{}", "AAA", &loc), "5:3:AAA");
+        assert_eq!(replace_arg("{seq}@{offset}{match}", "{}", "AAA", &loc), "2@30");
+        // The hole is not expanded
+        assert_eq!(replace_arg("[{}]", "{}", "{nr}", &loc), "[{nr}]");
+        // <replace-str> takes priority over the placeholders
+        assert_eq!(replace_arg("{nr}", "{nr}", "AAA", &loc), "AAA");
     }
 
     #[test]
-    fn test_messy_numbers() {
-        cfg_if::cfg_if! {
-            if #[cfg(windows)] {
-                static SED_CMD: &str = "C:\\\"Program Files\"\\Git\\usr\\bin\\sed.exe";
-            } else {
-                static SED_CMD: &str = "sed";
-            }
-        }
-        let (tx, rx) = mpsc::channel();
-        let (mut rx_messy_numbers, _) = exec_pipeline_mpsc_input(&format!("{} s/./3/", SED_CMD), rx).unwrap();
-        let input = b"abcdef\n".to_vec();
-        tx.send(input).unwrap();
-        let mut buf = Vec::with_capacity(16);
-        drop(tx);
-        rx_messy_numbers.read_until(b'\n', &mut buf).unwrap();
-        let line = String::from_utf8_lossy(&buf).to_string();
-        assert_eq!(line, "3bcdef\n");
+    fn test_shell_cmds() {
+        let args = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<String>>();
+        assert_eq!(shell_cmds("sh", &args(&["echo a | tr a b"])), vec!["sh", "-c", "echo a | tr a b"]);
+        assert_eq!(shell_cmds("/bin/zsh", &args(&["echo"])), vec!["/bin/zsh", "-c", "echo"]);
+        assert_eq!(shell_cmds("cmd", &args(&["echo"])), vec!["cmd", "/C", "echo"]);
+        // The arguments after the first one keep their quoting
+        assert_eq!(shell_cmds("sh", &args(&["printf", "%s\\n", "a b"])), vec!["sh", "-c", "printf '%s\\n' 'a b'"]);
+        assert_eq!(shell_cmds("cmd", &args(&["echo", "a b"])), vec!["cmd", "/C", "echo \"a b\""]);
     }
 
-    /// Example of noisey numbers (Read from BufReader):
-    /// ```
-    /// 1: test test"
-    /// 2-
-    ///     3@@@the line has spaces at beginning
-    ///     4!!!TAB character is also acceptable
-    /// 5
-    /// ```
-    /// Example of result (Receiver will get u64 numbers):
-    /// ```
-    /// 1
-    /// 2
-    /// 3
-    /// 4
-    /// 5
-    /// ```
     #[test]
-    fn test_clean_numbers() {
-        cfg_if::cfg_if! {
-            if #[cfg(windows)] {
-                static SED_CMD: &str = "C:\\\"Program Files\"\\Git\\usr\\bin\\sed.exe";
-            } else {
-                static SED_CMD: &str = "sed";
-            }
-        }
-        let (tx, rx) = mpsc::channel();
-        let (rx_messy_numbers, _) = exec_pipeline_mpsc_input(&format!("{} s/./3/", SED_CMD), rx).unwrap();
-        let input = b"abcdef\n".to_vec();
-        tx.send(input).unwrap();
-        drop(tx);
-        let (numbers, _) = clean_numbers(rx_messy_numbers, b'\n');
-        match numbers.recv() {
-            Ok(n) => {
-                assert_eq!(n, 3);
-            },
-            Err(_) => {
-                assert!(false);
-            },
-        };
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("sh", "abc.txt"), "abc.txt");
+        assert_eq!(shell_quote("sh", ""), "''");
+        assert_eq!(shell_quote("sh", "$(date); it's"), "'$(date); it'\\''s'");
+        assert_eq!(shell_quote("cmd", "a&b \"c\""), "\"a&b \"\"c\"\"\"");
     }
 }
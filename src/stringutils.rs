@@ -31,6 +31,38 @@ pub fn extract_number(line: String) -> Option<u64> {
     None
 }
 
+/// Part of the line which the pipeline of -e chooses
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Region {
+    Line,                // "N" or "N:..." (e.g. grep -n)
+    Chars(usize, usize), // "N:start-end", characters counted from 1
+    Bytes(usize, usize), // "N:bstart-end", bytes counted from 1
+    Field(usize),        // "N:fK"
+}
+
+// Extract line number and the region from string line.
+// The line must consist of only "N:start-end", "N:bstart-end" or "N:fK" to choose a region,
+// otherwise the leading number chooses the entire line.
+pub fn extract_region(line: String) -> Option<(u64, Region)> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"^\s*([0-9]+):(?:(b)?([0-9]+)-([0-9]+)|f([0-9]+))\s*$").unwrap();
+    }
+    let cap = match RE.captures(&line) {
+        Some(cap) => cap,
+        None => return extract_number(line).map(|i| (i, Region::Line)),
+    };
+    let num = |i: usize| cap.get(i).and_then(|m| m.as_str().parse::<usize>().ok());
+    let i: u64 = cap[1].parse().ok()?;
+    let region = match (cap.get(2), num(3), num(4), num(5)) {
+        (None, Some(start), Some(end), _) => Region::Chars(start, end),
+        (Some(_), Some(start), Some(end), _) => Region::Bytes(start, end),
+        (_, _, _, Some(k)) => Region::Field(k),
+        // Too big numbers
+        _ => return None,
+    };
+    Some((i, region))
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -52,4 +84,14 @@ mod test {
         assert_eq!(extract_number("43201613413".to_string()), Some(43201613413));
         assert_eq!(extract_number("-1".to_string()), None);
     }
+    #[test]
+    fn test_extract_region() {
+        assert_eq!(extract_region("12:3-5\n".to_string()), Some((12, Region::Chars(3, 5))));
+        assert_eq!(extract_region("12:b3-5".to_string()), Some((12, Region::Bytes(3, 5))));
+        assert_eq!(extract_region(" 7:f2 ".to_string()), Some((7, Region::Field(2))));
+        assert_eq!(extract_region("12:3-5 abc".to_string()), Some((12, Region::Line)));
+        assert_eq!(extract_region("12:f2x".to_string()), Some((12, Region::Line)));
+        assert_eq!(extract_region("12".to_string()), Some((12, Region::Line)));
+        assert_eq!(extract_region("abc".to_string()), None);
+    }
 }
@@ -0,0 +1,623 @@
+use super::chunk::{ChunkSendError, Emitter, Location, Tracker};
+use super::list::converter::to_ranges;
+use super::predicate::expr::Expr;
+use super::sed::address::Address;
+use super::selector::{self, Selector};
+use super::{onig, procs, REGEX_WS};
+use regex::Regex;
+use std::fs::File;
+use std::io::{Read, Write};
+
+/// Piece of the input given to `Tape::for_each`
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    Keep(String),                   // A string under the masking tape.
+    Hole(usize, String, Location),  // A string in the hole. The number is the index of the list given by `fields`.
+}
+
+/// Masking tape with holes, which divides the input into the chunks.
+///
+/// ```
+/// use teip::{Event, TapeBuilder};
+///
+/// let mut tape = TapeBuilder::new().fields("2").delimiter(",").build().unwrap();
+/// let mut out = Vec::new();
+/// tape.transform(&b"a,b,c\n"[..], &mut out, |hole, _| hole.to_uppercase()).unwrap();
+/// assert_eq!(out, b"a,B,c\n");
+///
+/// let mut holes = Vec::new();
+/// tape.for_each(&b"a,b,c\n"[..], |e| {
+///     if let Event::Hole(_, s, loc) = e {
+///         holes.push((s, loc.line));
+///     }
+/// }).unwrap();
+/// assert_eq!(holes, vec![("b".to_string(), 1)]);
+/// ```
+pub struct Tape {
+    selector: Box<dyn Selector>,
+    tracker: Tracker,
+    line_end: u8,
+    solid: bool,
+}
+
+impl Tape {
+    /// Create a tape whose holes are decided by `selector`.
+    /// If `solid` is false, a hole is divided at the line ends in it.
+    pub fn new(selector: Box<dyn Selector>, line_end: u8, solid: bool) -> Tape {
+        Tape {
+            selector,
+            tracker: Tracker::new(line_end),
+            line_end,
+            solid,
+        }
+    }
+
+    /// Line delimiter of the input
+    pub fn line_end(&self) -> u8 {
+        self.line_end
+    }
+
+    /// Read `input` to the end and send the chunks to `ch`.
+    /// `input` is given to another thread only if `external` is used.
+    pub fn feed<R: Read + Send + 'static>(&mut self, input: R, ch: &mut dyn Emitter) -> Result<(), ChunkSendError> {
+        self.tracker = Tracker::new(self.line_end);
+        let input = self.selector.open(Box::new(input), self.line_end).map_err(ChunkSendError::Input)?;
+        selector::drive(&mut *self.selector, input, ch, &mut self.tracker, self.line_end, self.solid)?;
+        ch.send_eof()
+    }
+
+    /// Same as `feed` but `external` reads the regular file again instead of keeping the lines in memory.
+    pub fn feed_file(&mut self, file: File, ch: &mut dyn Emitter) -> Result<(), ChunkSendError> {
+        self.tracker = Tracker::new(self.line_end);
+        self.feed_file_part(file, ch)?;
+        ch.send_eof()
+    }
+
+    /// Same as `feed_file` but the end of `file` is not told to `ch`, so that another file can follow.
+    /// The selection starts over at the beginning of each file (e.g. line numbers of `lines`), and so do
+    /// the lines and the offsets of the locations. The sequence numbers of the holes continue.
+    pub fn feed_file_part(&mut self, file: File, ch: &mut dyn Emitter) -> Result<(), ChunkSendError> {
+        self.tracker.start_over();
+        let input = self.selector.open_file(file, self.line_end).map_err(ChunkSendError::Input)?;
+        selector::drive(&mut *self.selector, input, ch, &mut self.tracker, self.line_end, self.solid)
+    }
+
+    /// Read `input` to the end and call `f` for each chunk.
+    pub fn for_each<R, F>(&mut self, input: R, f: F) -> Result<(), ChunkSendError>
+    where
+        R: Read + Send + 'static,
+        F: FnMut(Event),
+    {
+        let mut ch = EventEmitter { f };
+        self.feed(input, &mut ch)
+    }
+
+    /// Read `input` to the end and write it to `output`, replacing the holes with the result of `f`.
+    /// `f` receives the string in the hole and its location.
+    pub fn transform<R, W, F>(&mut self, input: R, output: W, f: F) -> Result<(), ChunkSendError>
+    where
+        R: Read + Send + 'static,
+        W: Write,
+        F: FnMut(&str, &Location) -> String,
+    {
+        let mut ch = TransformEmitter {
+            writer: output,
+            f,
+        };
+        self.feed(input, &mut ch)?;
+        ch.writer.flush().map_err(ChunkSendError::Pipe)
+    }
+}
+
+/// Builds a `Tape` with various configuration knobs.
+///
+/// Selections are prioritized in the order of `lines`, `regex`, `sed`, `external`, `chars`
+/// and `fields` if more than one of them are given. `predicate` narrows any of them down.
+#[derive(Debug, Default, Clone)]
+pub struct TapeBuilder {
+    lines: Option<String>,
+    regex: Option<String>,
+    only_matched: bool,
+    onig: bool,
+    context: Option<(usize, usize)>,
+    sed: Option<String>,
+    predicate: Option<String>,
+    external: Option<String>,
+    reorder: usize,
+    chars: Option<String>,
+    fields: Vec<String>,
+    delimiter: Option<String>,
+    regex_delimiter: Option<String>,
+    csv: bool,
+    invert: bool,
+    zero: bool,
+    solid: bool,
+}
+
+impl TapeBuilder {
+    /// Create a new builder.
+    pub fn new() -> TapeBuilder {
+        TapeBuilder::default()
+    }
+
+    /// Act on these lines (-l)
+    pub fn lines(&mut self, list: &str) -> &mut TapeBuilder {
+        self.lines = Some(list.to_string());
+        self
+    }
+
+    /// Act on lines that match the regular expression (-g)
+    pub fn regex(&mut self, pattern: &str) -> &mut TapeBuilder {
+        self.regex = Some(pattern.to_string());
+        self
+    }
+
+    /// `regex` acts on only matched parts (-o)
+    pub fn only_matched(&mut self, yes: bool) -> &mut TapeBuilder {
+        self.only_matched = yes;
+        self
+    }
+
+    /// `regex` interprets Oniguruma regular expressions (-G)
+    pub fn onig(&mut self, yes: bool) -> &mut TapeBuilder {
+        self.onig = yes;
+        self
+    }
+
+    /// `regex` also acts on `before` lines before and `after` lines after the matched lines (-B, -A, -C).
+    /// `only_matched` is ignored.
+    pub fn context(&mut self, before: usize, after: usize) -> &mut TapeBuilder {
+        self.context = Some((before, after));
+        self
+    }
+
+    /// Act on lines selected by the address of sed like `/BEGIN/,/END/` (--sed)
+    pub fn sed(&mut self, address: &str) -> &mut TapeBuilder {
+        self.sed = Some(address.to_string());
+        self
+    }
+
+    /// Act on only lines which satisfy the expression like `$3 > 500 && $7 ~ /POST/` (--where).
+    /// The fields are split in the same way as `fields`. The other selections act on those lines,
+    /// or the entire lines are the holes if no other selection is given.
+    pub fn predicate(&mut self, expr: &str) -> &mut TapeBuilder {
+        self.predicate = Some(expr.to_string());
+        self
+    }
+
+    /// Act on lines whose numbers are printed by the pipeline (-e)
+    pub fn external(&mut self, pipeline: &str) -> &mut TapeBuilder {
+        self.external = Some(pipeline.to_string());
+        self
+    }
+
+    /// `external` sorts up to `size` numbers printed by the pipeline before using them (--reorder).
+    /// `usize::MAX` waits for the pipeline to finish and sorts all of them.
+    pub fn reorder(&mut self, size: usize) -> &mut TapeBuilder {
+        self.reorder = size;
+        self
+    }
+
+    /// Act on these characters (-c)
+    pub fn chars(&mut self, list: &str) -> &mut TapeBuilder {
+        self.chars = Some(list.to_string());
+        self
+    }
+
+    /// Act on these fields (-f).
+    /// Each call adds another list, and the holes tell which list they are made from.
+    pub fn fields(&mut self, list: &str) -> &mut TapeBuilder {
+        self.fields.push(list.to_string());
+        self
+    }
+
+    /// Field delimiter of `fields` (-d)
+    pub fn delimiter(&mut self, delimiter: &str) -> &mut TapeBuilder {
+        self.delimiter = Some(delimiter.to_string());
+        self
+    }
+
+    /// Regular expression for the field delimiter of `fields` (-D)
+    pub fn regex_delimiter(&mut self, pattern: &str) -> &mut TapeBuilder {
+        self.regex_delimiter = Some(pattern.to_string());
+        self
+    }
+
+    /// `fields` interprets the list as field numbers of CSV (--csv)
+    pub fn csv(&mut self, yes: bool) -> &mut TapeBuilder {
+        self.csv = yes;
+        self
+    }
+
+    /// Invert the range of the holes (-v)
+    pub fn invert(&mut self, yes: bool) -> &mut TapeBuilder {
+        self.invert = yes;
+        self
+    }
+
+    /// Line delimiter is NUL instead of a newline (-z)
+    pub fn zero(&mut self, yes: bool) -> &mut TapeBuilder {
+        self.zero = yes;
+        self
+    }
+
+    /// A field of CSV which contains newlines is a single hole (-s)
+    pub fn solid(&mut self, yes: bool) -> &mut TapeBuilder {
+        self.solid = yes;
+        self
+    }
+
+    /// Build a `Tape` from this configuration.
+    pub fn build(&self) -> Result<Tape, String> {
+        // If -z option is specified, change regex mode and line end
+        let (regex_mode, line_end) = if self.zero { ("(?ms)", b'\0') } else { ("", b'\n') };
+        let ranges = |list: &str| to_ranges(list, self.invert);
+        let regex = |pattern: &str| Regex::new(&(regex_mode.to_string() + pattern)).map_err(|e| e.to_string());
+        let onig_regex = |pattern: &str| -> Result<onig::Regex, String> {
+            if self.zero {
+                onig::new_option_multiline_regex(pattern)
+            } else {
+                onig::new_option_none_regex(pattern)
+            }
+        };
+        // Fields of --where and "N:fK" of -e are split in the same way as `fields`
+        let splitter = || -> Result<procs::FieldSplitter, String> {
+            Ok(if self.csv {
+                procs::FieldSplitter::Csv
+            } else if let Some(ref delim) = self.delimiter {
+                procs::FieldSplitter::Delimiter(delim.to_string())
+            } else if let Some(ref pattern) = self.regex_delimiter {
+                procs::FieldSplitter::Regex(regex(pattern)?)
+            } else {
+                procs::FieldSplitter::Regex(REGEX_WS.clone())
+            })
+        };
+        let selector: Option<Box<dyn Selector>> = if let Some(ref list) = self.lines {
+            Some(Box::new(procs::LineSelector::new(ranges(list)?)))
+        } else if let (Some(ref pattern), Some((before, after))) = (&self.regex, self.context) {
+            let matcher: Box<dyn Selector> = if self.onig {
+                Box::new(onig::OnigLineSelector::new(onig_regex(pattern)?, false))
+            } else {
+                Box::new(procs::RegexLineSelector::new(regex(pattern)?, false))
+            };
+            Some(Box::new(procs::ContextSelector::new(matcher, before, after, self.invert)))
+        } else if let Some(ref pattern) = self.regex {
+            Some(match (self.onig, self.only_matched) {
+                (false, false) => Box::new(procs::RegexLineSelector::new(regex(pattern)?, self.invert)),
+                (false, true) => Box::new(procs::RegexSelector::new(regex(pattern)?, self.invert)),
+                (true, only) => {
+                    // If -G option is specified, change regex engine
+                    let re = onig_regex(pattern)?;
+                    if only {
+                        Box::new(onig::OnigSelector::new(re, self.invert))
+                    } else {
+                        Box::new(onig::OnigLineSelector::new(re, self.invert))
+                    }
+                }
+            })
+        } else if let Some(ref address) = self.sed {
+            Some(Box::new(procs::SedSelector::new(Address::parse(address, regex)?, self.invert)))
+        } else if let Some(ref pipeline) = self.external {
+            Some(Box::new(procs::ExoffloadSelector::new(pipeline.to_string(), splitter()?, self.reorder, self.invert)))
+        } else if let Some(ref list) = self.chars {
+            Some(Box::new(procs::CharSelector::new(ranges(list)?)))
+        } else if !self.fields.is_empty() {
+            let targets = self
+                .fields
+                .iter()
+                .map(|list| ranges(list))
+                .collect::<Result<Vec<_>, _>>()?;
+            Some(if self.csv {
+                Box::new(procs::CsvSelector::new(targets))
+            } else if let Some(ref delim) = self.delimiter {
+                Box::new(procs::FieldSelector::new(targets, delim.to_string()))
+            } else if let Some(ref pattern) = self.regex_delimiter {
+                Box::new(procs::FieldRegexSelector::new(targets, regex(pattern)?))
+            } else {
+                Box::new(procs::FieldRegexSelector::new(targets, REGEX_WS.clone()))
+            })
+        } else {
+            None
+        };
+        // --where leaves the invert to the other selection if any
+        let selector = match (&self.predicate, selector) {
+            (Some(expr), selector) => {
+                let invert = self.invert && selector.is_none();
+                Box::new(procs::WhereSelector::new(Expr::parse(expr, regex)?, splitter()?, selector, invert))
+            }
+            (None, Some(selector)) => selector,
+            (None, None) => {
+                return Err("Any of lines, regex, sed, external, chars, fields or predicate is required".to_string())
+            }
+        };
+        Ok(Tape::new(selector, line_end, self.solid))
+    }
+}
+
+/// Emitter which calls the closure with the events (Tape::for_each)
+struct EventEmitter<F: FnMut(Event)> {
+    f: F,
+}
+
+impl<F: FnMut(Event)> Emitter for EventEmitter<F> {
+    fn send_keep(&mut self, msg: String) -> Result<(), ChunkSendError> {
+        (self.f)(Event::Keep(msg));
+        Ok(())
+    }
+
+    fn send_byps_to(&mut self, target: usize, msg: String, loc: Location) -> Result<(), ChunkSendError> {
+        (self.f)(Event::Hole(target, msg, loc));
+        Ok(())
+    }
+
+    fn send_eof(&mut self) -> Result<(), ChunkSendError> {
+        Ok(())
+    }
+}
+
+/// Emitter which writes the chunks replacing the holes (Tape::transform)
+struct TransformEmitter<W: Write, F: FnMut(&str, &Location) -> String> {
+    writer: W,
+    f: F,
+}
+
+impl<W: Write, F: FnMut(&str, &Location) -> String> Emitter for TransformEmitter<W, F> {
+    fn send_keep(&mut self, msg: String) -> Result<(), ChunkSendError> {
+        self.writer.write_all(msg.as_bytes()).map_err(ChunkSendError::Pipe)
+    }
+
+    fn send_byps_to(&mut self, _target: usize, msg: String, loc: Location) -> Result<(), ChunkSendError> {
+        let result = (self.f)(&msg, &loc);
+        self.writer.write_all(result.as_bytes()).map_err(ChunkSendError::Pipe)
+    }
+
+    fn send_eof(&mut self) -> Result<(), ChunkSendError> {
+        Ok(())
+    }
+
+    fn send_flush(&mut self) -> Result<(), ChunkSendError> {
+        self.writer.flush().map_err(ChunkSendError::Pipe)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::chunk::Index;
+    use crate::selector::Span;
+
+    fn events(builder: &TapeBuilder, input: &'static str) -> Vec<Event> {
+        let mut v = Vec::new();
+        builder.build().unwrap().for_each(input.as_bytes(), |e| v.push(e)).unwrap();
+        v
+    }
+
+    #[test]
+    fn test_for_each() {
+        let e = events(TapeBuilder::new().regex("b+").only_matched(true), "abbc\n");
+        assert_eq!(e[0], Event::Keep("a".to_string()));
+        assert_eq!(
+            e[1],
+            Event::Hole(0, "bb".to_string(), Location { seq: 1, line: 1, byte_offset: 1, index: Index::Match(1) })
+        );
+        assert_eq!(e[2], Event::Keep("c".to_string()));
+        assert_eq!(e[3], Event::Keep("\n".to_string()));
+    }
+
+    #[test]
+    fn test_for_each_multi_fields() {
+        let e = events(TapeBuilder::new().fields("1").fields("3"), "a b c\n");
+        let holes: Vec<(usize, String)> = e
+            .into_iter()
+            .filter_map(|e| match e {
+                Event::Hole(t, s, _) => Some((t, s)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(holes, vec![(0, "a".to_string()), (1, "c".to_string())]);
+    }
+
+    #[test]
+    fn test_transform() {
+        let mut tape = TapeBuilder::new().lines("2").build().unwrap();
+        let mut out = Vec::new();
+        tape.transform("a\nb\nc\n".as_bytes(), &mut out, |s, loc| format!("{}{}", s, loc.line))
+            .unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "a\nb2\nc\n");
+    }
+
+    #[test]
+    fn test_context() {
+        let lines = |before: usize, after: usize, input: &'static str| -> Vec<u64> {
+            events(TapeBuilder::new().regex("x").context(before, after), input)
+                .into_iter()
+                .filter_map(|e| match e {
+                    Event::Hole(_, _, loc) => Some(loc.line),
+                    _ => None,
+                })
+                .collect()
+        };
+        let input = "a\nb\nx\nc\nd\ne\nf\nx\ng\n";
+        assert_eq!(lines(0, 0, input), vec![3, 8]);
+        assert_eq!(lines(0, 1, input), vec![3, 4, 8, 9]);
+        assert_eq!(lines(2, 0, input), vec![1, 2, 3, 6, 7, 8]);
+        // The contexts overlap
+        assert_eq!(lines(3, 2, input), vec![1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        // More than the input
+        assert_eq!(lines(10, 10, "a\nx\nb"), vec![1, 2, 3]);
+        assert_eq!(lines(1, 1, "a\nb\n"), Vec::<u64>::new());
+    }
+
+    #[test]
+    fn test_sed_last() {
+        let lines = |address: &str, input: &'static str| -> Vec<u64> {
+            events(TapeBuilder::new().sed(address), input)
+                .into_iter()
+                .filter_map(|e| match e {
+                    Event::Hole(_, _, loc) => Some(loc.line),
+                    _ => None,
+                })
+                .collect()
+        };
+        assert_eq!(lines("$", "a\nb\nc\n"), vec![3]);
+        assert_eq!(lines("$", "a\nb\nc"), vec![3]);
+        assert_eq!(lines("/b/,$!", "a\nb\nc\n"), vec![1]);
+        assert_eq!(lines("$", ""), Vec::<u64>::new());
+    }
+
+    #[test]
+    fn test_predicate() {
+        let holes = |builder: &mut TapeBuilder, input: &'static str| -> Vec<(String, u64)> {
+            events(builder, input)
+                .into_iter()
+                .filter_map(|e| match e {
+                    Event::Hole(_, s, loc) => Some((s, loc.line)),
+                    _ => None,
+                })
+                .collect()
+        };
+        let input = "a 100 GET\nb 900 POST\nc 700 GET\n";
+        assert_eq!(
+            holes(TapeBuilder::new().predicate("$2 > 500"), input),
+            vec![("b 900 POST".to_string(), 2), ("c 700 GET".to_string(), 3)]
+        );
+        assert_eq!(
+            holes(TapeBuilder::new().predicate("$2 > 500").invert(true), input),
+            vec![("a 100 GET".to_string(), 1)]
+        );
+        // -v is for -f
+        assert_eq!(
+            holes(TapeBuilder::new().predicate("$3 ~ /GET/").fields("2-").invert(true), input),
+            vec![("a".to_string(), 1), ("c".to_string(), 3)]
+        );
+        // -l counts the lines which do not satisfy it as well
+        assert_eq!(holes(TapeBuilder::new().predicate("$3 == \"GET\"").lines("2-"), input), vec![("c 700 GET".to_string(), 3)]);
+        assert_eq!(
+            holes(TapeBuilder::new().predicate("$2 == \"x,y\" && NF == 3").fields("1").csv(true), "a,\"x,y\",b\nc,x,y\n"),
+            vec![("a".to_string(), 1)]
+        );
+        // Multiline record of CSV
+        assert_eq!(
+            holes(TapeBuilder::new().predicate("$2 ~ /\\n/").csv(true), "a,\"b\nc\"\nd,e\n"),
+            vec![("a,\"b".to_string(), 1), ("c\"".to_string(), 2)]
+        );
+        assert_eq!(holes(TapeBuilder::new().predicate("NF == 3").delimiter(":"), "a:b:c\na:b\n"), vec![("a:b:c".to_string(), 1)]);
+    }
+
+    #[test]
+    fn test_csv_multiline_record() {
+        let holes = |solid: bool| -> Vec<(String, u64)> {
+            events(TapeBuilder::new().fields("2").csv(true).solid(solid), "a,\"b\nc\",d\ne,f\n")
+                .into_iter()
+                .filter_map(|e| match e {
+                    Event::Hole(_, s, loc) => Some((s, loc.line)),
+                    _ => None,
+                })
+                .collect()
+        };
+        assert_eq!(holes(true), vec![("\"b\nc\"".to_string(), 1), ("f".to_string(), 3)]);
+        assert_eq!(
+            holes(false),
+            vec![("\"b".to_string(), 1), ("c\"".to_string(), 2), ("f".to_string(), 3)]
+        );
+    }
+
+    /// Holes made by both of the selectors
+    struct Union(Box<dyn Selector>, Box<dyn Selector>);
+
+    impl Selector for Union {
+        fn select(&mut self, record: &str) -> Vec<Span> {
+            let mut spans = self.0.select(record);
+            spans.extend(self.1.select(record));
+            spans.sort_by_key(|s| s.start);
+            spans
+        }
+    }
+
+    #[test]
+    fn test_custom_selector() {
+        let re = |p: &str| Regex::new(p).unwrap();
+        let selector = Union(
+            Box::new(procs::RegexSelector::new(re("[0-9]+"), false)),
+            Box::new(procs::RegexSelector::new(re("[A-Z]+"), false)),
+        );
+        let mut tape = Tape::new(Box::new(selector), b'\n', false);
+        let mut out = Vec::new();
+        tape.transform("ab12CD\nEF3\n".as_bytes(), &mut out, |s, _| format!("[{}]", s))
+            .unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "ab[12][CD]\n[EF][3]\n");
+    }
+
+    #[test]
+    fn test_build_error() {
+        assert!(TapeBuilder::new().build().is_err());
+        assert!(TapeBuilder::new().chars("x").build().is_err());
+        assert!(TapeBuilder::new().regex("(").build().is_err());
+        if cfg!(feature = "oniguruma") {
+            assert!(TapeBuilder::new().regex("(").onig(true).build().is_err());
+        } else {
+            assert!(TapeBuilder::new().regex("a").onig(true).build().is_err());
+        }
+    }
+
+    /// Reader which fails after the given lines
+    struct Broken(Option<&'static [u8]>);
+
+    impl Read for Broken {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let lines = self.0.take().ok_or_else(|| std::io::Error::other("broken"))?;
+            buf[..lines.len()].copy_from_slice(lines);
+            Ok(lines.len())
+        }
+    }
+
+    #[test]
+    fn test_read_error() {
+        let mut tape = TapeBuilder::new().lines("1").build().unwrap();
+        let mut holes = Vec::new();
+        let result = tape.for_each(Broken(Some(b"a\n")), |e| holes.push(e));
+        assert!(matches!(result, Err(ChunkSendError::Input(ref e)) if e.to_string() == "broken"));
+        assert_eq!(holes.len(), 2);
+    }
+
+    /// Emitter which records the chunks and the flushes
+    #[derive(Default)]
+    struct Recorder(Vec<String>);
+
+    impl Emitter for Recorder {
+        fn send_keep(&mut self, msg: String) -> Result<(), ChunkSendError> {
+            self.0.push(msg);
+            Ok(())
+        }
+
+        fn send_byps_to(&mut self, _target: usize, msg: String, _loc: Location) -> Result<(), ChunkSendError> {
+            self.0.push(format!("[{}]", msg));
+            Ok(())
+        }
+
+        fn send_eof(&mut self) -> Result<(), ChunkSendError> {
+            Ok(())
+        }
+
+        fn send_flush(&mut self) -> Result<(), ChunkSendError> {
+            self.0.push("FLUSH".to_string());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_context_idle() {
+        let chunks = |before: usize, after: usize| -> Vec<String> {
+            let mut tape = TapeBuilder::new().regex("x").context(before, after).build().unwrap();
+            let mut ch = Recorder::default();
+            // The next read blocks until the input ends, which is emulated by the error
+            assert!(tape.feed(Broken(Some(b"a\nx\nb\nc\n")), &mut ch).is_err());
+            ch.0
+        };
+        // The lines are printed before the next read as soon as it is known whether they are holes
+        assert_eq!(chunks(1, 0), vec!["FLUSH", "[a]", "\n", "[x]", "\n", "b", "\n", "FLUSH"]);
+        assert_eq!(chunks(0, 1), vec!["FLUSH", "a", "\n", "[x]", "\n", "[b]", "\n", "c", "\n", "FLUSH"]);
+        // "c" is a hole if the next line is matched
+        assert_eq!(chunks(2, 0), vec!["FLUSH", "[a]", "\n", "[x]", "\n", "FLUSH"]);
+    }
+}
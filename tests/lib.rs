@@ -105,6 +105,520 @@ mod cmdtest {
             .stdout("ABC\nDFE\n\nCCA\n");
     }
 
+    #[test]
+    fn test_exoffload_chars() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        let mcmd = format!("{} 2:2-3", ESCAPE_ECHO_CMD);
+        cmd.args(&["-e", &mcmd, SED_CMD, "s/./@/g"])
+            .write_stdin("ABC\nDFE\nBCC\n")
+            .assert()
+            .stdout("ABC\nD@@\nBCC\n");
+    }
+
+    #[test]
+    fn test_exoffload_bytes() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        let mcmd = format!("{} 1:b2-3", ESCAPE_ECHO_CMD);
+        // The hole is extended to the whole character. tr replaces each byte regardless of the locale.
+        cmd.args(&["-e", &mcmd, "--", TR_CMD, "-c", "\\n", "@"])
+            .write_stdin("aあb\n")
+            .assert()
+            .stdout("a@@@b\n");
+    }
+
+    #[test]
+    fn test_exoffload_field() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        let mcmd = format!("{} 2:f2", ESCAPE_ECHO_CMD);
+        cmd.args(&["-d", ",", "-e", &mcmd, SED_CMD, "s/./@/g"])
+            .write_stdin("a,b,c\nd,e,f\n")
+            .assert()
+            .stdout("a,b,c\nd,@,f\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_exoffload_regions() {
+        let mcmd = "printf '1:f3\\n1:1-1\\n1:1-2\\n2:2-3\\n2\\n'";
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-e", mcmd, SED_CMD, "s/./@/g"])
+            .write_stdin("ab cd ef\nghi\n")
+            .assert()
+            .stdout("@@ cd @@\n@@@\n");
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-v", "-e", mcmd, SED_CMD, "s/./@/g"])
+            .write_stdin("ab cd ef\nghi\n")
+            .assert()
+            .stdout("ab@@@@ef\nghi\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_exoffload_reorder() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["--reorder", "all", "-e", "printf '4\\n1\\n4\\n2\\n'", SED_CMD, "s/./@/"])
+            .write_stdin("AAA\nBBB\nCCC\nDDD\n")
+            .assert()
+            .stdout("@AA\n@BB\nCCC\n@DD\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_exoffload_reorder_window() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["--reorder", "1", "-e", "printf '2\\n1\\n4\\n3\\n'", SED_CMD, "s/./@/"])
+            .write_stdin("AAA\nBBB\nCCC\nDDD\nEEE\n")
+            .assert()
+            .stdout("@AA\n@BB\n@CC\n@DD\nEEE\n");
+    }
+
+    #[test]
+    fn test_reorder_without_exoffload() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["--reorder", "all", "-l", "1"])
+            .write_stdin("AAA\n")
+            .assert()
+            .code(1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_exoffload_file() {
+        // The standard input is a regular file which has been read partly
+        let path = std::env::temp_dir().join(format!("teip-test-{}.txt", std::process::id()));
+        std::fs::write(&path, "SKIP\nABC\nDFE\nBCC\nCCA\n").unwrap();
+        let bin = assert_cmd::cargo::cargo_bin(env!("CARGO_PKG_NAME"));
+        let mut cmd = assert_cmd::Command::new("sh");
+        cmd.args(&["-c", "{ dd bs=5 count=1 >/dev/null 2>&1; \"$0\" -e 'grep -n A' -- sed s/./@/; } < \"$1\""])
+            .arg(&bin)
+            .arg(&path)
+            .assert()
+            .stdout("@BC\nDFE\nBCC\n@CA\n");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_files() {
+        let dir = std::env::temp_dir().join(format!("teip-test-{}-files", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.txt"), "AAA\nBBB\n").unwrap();
+        std::fs::write(dir.join("b.txt"), "CCC\nDDD\n").unwrap();
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.current_dir(&dir)
+            .args(&["-l", "2", "--", SED_CMD, "s/./@/", "::::", "a.txt", "b.txt"])
+            .write_stdin("IGNORED\n")
+            .assert()
+            .stdout("AAA\n@BB\nCCC\n@DD\n");
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.current_dir(&dir)
+            .args(&["-l", "2", "--continuous", "--", SED_CMD, "s/./@/", "::::", "a.txt", "b.txt"])
+            .assert()
+            .stdout("AAA\n@BB\nCCC\nDDD\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_files_command_args() {
+        // The existing file given to the command is not the input
+        let dir = std::env::temp_dir().join(format!("teip-test-{}-files-command-args", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("suffix.txt"), "SUFFIX\n").unwrap();
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.current_dir(&dir)
+            .args(&["-l", "1", "--", "sh", "-c", "sed \"s/$/-$(cat \"$0\")/\"", "suffix.txt"])
+            .write_stdin("a\nb\n")
+            .assert()
+            .stdout("a-SUFFIX\nb\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_files_list() {
+        let dir = std::env::temp_dir().join(format!("teip-test-{}-files-list", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.txt"), "AAA\nBBB\n").unwrap();
+        std::fs::write(dir.join("b.txt"), "CCC\nDDD\n").unwrap();
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.current_dir(&dir)
+            .args(&["-l", "1", "--files", "-", "--", SED_CMD, "s/./@/", "::::", "a.txt"])
+            .write_stdin("b.txt\n")
+            .assert()
+            .stdout("@AA\nBBB\n@CC\nDDD\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_files_exoffload() {
+        // The pipeline is executed for each file
+        let dir = std::env::temp_dir().join(format!("teip-test-{}-files-exoffload", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.txt"), "ABC\nDEF\n").unwrap();
+        std::fs::write(dir.join("b.txt"), "GHI\nBCD\n").unwrap();
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.current_dir(&dir)
+            .args(&["-e", "grep -n B", "--", SED_CMD, "s/./@/", "::::", "a.txt", "b.txt"])
+            .assert()
+            .stdout("@BC\nDEF\nGHI\n@CD\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_files_not_found() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-l", "1", "::::", "teip-test-not-found.txt"])
+            .assert()
+            .code(1);
+    }
+
+    #[test]
+    fn test_continuous_without_files() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-l", "1", "--continuous"])
+            .write_stdin("AAA\n")
+            .assert()
+            .code(1);
+    }
+
+    #[test]
+    fn test_dry_run_json() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["--dry-run-format", "json", "-og", "\\d+"])
+            .write_stdin("ab12\ncd345\n")
+            .assert()
+            .stdout(concat!(
+                r#"{"file":"-","seq":1,"line":1,"offset":{"byte":2,"char":2},"column":{"byte":2,"char":2},"field":null,"match":1,"text":"12"}"#,
+                "\n",
+                r#"{"file":"-","seq":2,"line":2,"offset":{"byte":7,"char":7},"column":{"byte":2,"char":2},"field":null,"match":1,"text":"345"}"#,
+                "\n",
+            ));
+    }
+
+    #[test]
+    fn test_dry_run_json_field() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["--dry-run-format", "json", "-f", "2", "-d", ","])
+            .write_stdin("あい,\"う\"\n")
+            .assert()
+            .stdout(concat!(
+                r#"{"file":"-","seq":1,"line":1,"offset":{"byte":7,"char":3},"column":{"byte":7,"char":3},"field":2,"match":null,"text":"\"う\""}"#,
+                "\n",
+            ));
+    }
+
+    #[test]
+    fn test_dry_run_color_auto() {
+        // The output is not a terminal
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-og", "\\d+"])
+            .write_stdin("ab12 34\n")
+            .assert()
+            .stdout("ab[12] [34]\n");
+    }
+
+    #[test]
+    fn test_dry_run_color_always() {
+        // Adjacent holes are in different colors
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["--color", "always", "-og", "\\d+"])
+            .env("NO_COLOR", "1")
+            .env_remove("TEIP_HIGHLIGHT")
+            .env_remove("TEIP_HIGHLIGHT_ALT")
+            .write_stdin("ab12 34\n")
+            .assert()
+            .stdout(concat!(
+                "ab\x1b[36m[\x1b[0m\x1b[01;31m12\x1b[0m\x1b[36m]\x1b[0m ",
+                "\x1b[36m[\x1b[0m\x1b[01;33m34\x1b[0m\x1b[36m]\x1b[0m\n"
+            ));
+    }
+
+    #[test]
+    fn test_dry_run_color_never() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["--color=never", "-og", "\\d+"])
+            .env("TEIP_HIGHLIGHT", "<{}>")
+            .write_stdin("ab12 34\n")
+            .assert()
+            .stdout("ab[12] [34]\n");
+    }
+
+    #[test]
+    fn test_dry_run_highlight_alt() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["--color=always", "-og", "\\d+"])
+            .env("TEIP_HIGHLIGHT", "<{}>")
+            .env("TEIP_HIGHLIGHT_ALT", "({})")
+            .write_stdin("ab12 34 5\n")
+            .assert()
+            .stdout("ab<12> (34) <5>\n");
+        // The alternate holes do not follow TEIP_HIGHLIGHT
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["--color=always", "-og", "\\d+"])
+            .env("TEIP_HIGHLIGHT", "<{}>")
+            .env_remove("TEIP_HIGHLIGHT_ALT")
+            .write_stdin("ab12 34\n")
+            .assert()
+            .stdout("ab<12> \x1b[36m[\x1b[0m\x1b[01;33m34\x1b[0m\x1b[36m]\x1b[0m\n");
+    }
+
+    #[test]
+    fn test_hole_index() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["--hole-index", "-og", "\\d+"])
+            .write_stdin("ab12 34\ncd5\n")
+            .assert()
+            .stdout("ab[1:12] [2:34]\ncd[3:5]\n");
+    }
+
+    #[test]
+    fn test_hole_index_with_command() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["--hole-index", "-l", "1", "--", SED_CMD, "s/./@/"])
+            .write_stdin("AAA\n")
+            .assert()
+            .code(1);
+    }
+
+    #[test]
+    fn test_dry_run_format_with_command() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["--dry-run-format", "json", "-l", "1", "--", SED_CMD, "s/./@/"])
+            .write_stdin("AAA\n")
+            .assert()
+            .code(1);
+    }
+
+    #[test]
+    fn test_diff() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["--diff", "-l", "2", "--", SED_CMD, "s/./@/"])
+            .write_stdin("AAA\nBBB\nCCC\n")
+            .assert()
+            .stdout("--- -\n+++ -\n@@ -1,3 +1,3 @@\n AAA\n-BBB\n+@BB\n CCC\n");
+    }
+
+    #[test]
+    fn test_diff_files() {
+        // Unchanged files are not printed
+        let dir = std::env::temp_dir().join(format!("teip-test-{}-diff-files", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.txt"), "ABC\nDEF\n").unwrap();
+        std::fs::write(dir.join("b.txt"), "GHI\n").unwrap();
+        std::fs::write(dir.join("c.txt"), "XYZ\nDEF").unwrap();
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.current_dir(&dir)
+            .args(&["--diff", "-g", "DEF", "--", SED_CMD, "s/./@/", "::::", "a.txt", "b.txt", "c.txt"])
+            .assert()
+            .success()
+            .stdout(concat!(
+                "--- a/a.txt\n+++ b/a.txt\n@@ -1,2 +1,2 @@\n ABC\n-DEF\n+@EF\n",
+                "--- a/c.txt\n+++ b/c.txt\n@@ -1,2 +1,2 @@\n XYZ\n-DEF\n\\ No newline at end of file\n+@EF\n\\ No newline at end of file\n",
+            ));
+        assert_eq!(std::fs::read_to_string(dir.join("a.txt")).unwrap(), "ABC\nDEF\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_diff_without_command() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["--diff", "-l", "1"])
+            .write_stdin("AAA\n")
+            .assert()
+            .code(1);
+    }
+
+    #[test]
+    fn test_in_place() {
+        let dir = std::env::temp_dir().join(format!("teip-test-{}-in-place", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.txt"), "ABC\nDEF\nAGH\n").unwrap();
+        std::fs::write(dir.join("b.txt"), "XYZ\nAAA\n").unwrap();
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.current_dir(&dir)
+            .args(&["-i", "-g", "A", "--", SED_CMD, "s/./@/", "::::", "a.txt", "b.txt"])
+            .assert()
+            .success()
+            .stdout("");
+        assert_eq!(std::fs::read_to_string(dir.join("a.txt")).unwrap(), "@BC\nDEF\n@GH\n");
+        assert_eq!(std::fs::read_to_string(dir.join("b.txt")).unwrap(), "XYZ\n@AA\n");
+        // No temporary file is left
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_in_place_backup() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+        let dir = std::env::temp_dir().join(format!("teip-test-{}-in-place-backup", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("a.txt");
+        std::fs::write(&path, "ABC\nDEF\n").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o640)).unwrap();
+        let ino = std::fs::metadata(&path).unwrap().ino();
+        // The old backup is replaced
+        std::fs::write(dir.join("a.txt.bak"), "OLD\n").unwrap();
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-i.bak", "-l", "2", "--builtin", "lower", "::::"])
+            .arg(&path)
+            .assert()
+            .success();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "ABC\ndef\n");
+        assert_eq!(std::fs::read_to_string(dir.join("a.txt.bak")).unwrap(), "ABC\nDEF\n");
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+        // The original file itself is kept as the backup, not a copy of it
+        assert_eq!(std::fs::metadata(dir.join("a.txt.bak")).unwrap().ino(), ino);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_in_place_failure() {
+        // Stop at the first file whose command fails, leaving it as it is
+        let dir = std::env::temp_dir().join(format!("teip-test-{}-in-place-failure", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.txt"), "ABC\n").unwrap();
+        std::fs::write(dir.join("b.txt"), "DEF\n").unwrap();
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.current_dir(&dir)
+            .args(&["-i", "-l", "1", "--", "sh", "-c", "tr A-Z a-z; exit 1", "::::", "a.txt", "b.txt"])
+            .assert()
+            .code(123);
+        assert_eq!(std::fs::read_to_string(dir.join("a.txt")).unwrap(), "ABC\n");
+        assert_eq!(std::fs::read_to_string(dir.join("b.txt")).unwrap(), "DEF\n");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_in_place_without_files() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-i", "-l", "1", "--", SED_CMD, "s/./@/"])
+            .write_stdin("AAA\n")
+            .assert()
+            .code(1);
+    }
+
+    #[test]
+    fn test_context_after() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-g", "B", "-A", "2", SED_CMD, "s/./@/"])
+            .write_stdin("A\nB\nC\nD\nE\nF\nG\n")
+            .assert()
+            .stdout("A\n@\n@\n@\nE\nF\nG\n");
+    }
+
+    #[test]
+    fn test_context_before_after() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-g", "D", "-A", "1", "-B", "2", SED_CMD, "s/./@/"])
+            .write_stdin("A\nB\nC\nD\nE\nF\nG\n")
+            .assert()
+            .stdout("A\n@\n@\n@\n@\nF\nG\n");
+    }
+
+    #[test]
+    fn test_context_center_invert() {
+        // -A takes priority over -C
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-v", "-g", "^[DE]$", "-C", "1", "-A", "0", SED_CMD, "s/./@/"])
+            .write_stdin("A\nB\nC\nD\nE\nF\nG\n")
+            .assert()
+            .stdout("@\n@\nC\nD\nE\n@\n@\n");
+    }
+
+    #[test]
+    fn test_context_zero() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-z", "-g", "B", "-B", "1", "--", SED_CMD, "-z", "s/./@/"])
+            .write_stdin("A\0B\0C\0")
+            .assert()
+            .stdout("@\0@\0C\0");
+    }
+
+    #[test]
+    fn test_sed_range() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["--sed", "/B/,/D/", "--", SED_CMD, "s/./@/"])
+            .write_stdin("A\nB\nC\nD\nE\nB\n")
+            .assert()
+            .stdout("A\n@\n@\n@\nE\n@\n");
+    }
+
+    #[test]
+    fn test_sed_step() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["--sed", "1~3", "--", SED_CMD, "s/./@/"])
+            .write_stdin("A\nB\nC\nD\nE\nF\nG\n")
+            .assert()
+            .stdout("@\nB\nC\n@\nE\nF\n@\n");
+    }
+
+    #[test]
+    fn test_sed_last_negated() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["--sed", "$!", "--", SED_CMD, "s/./@/"])
+            .write_stdin("A\nB\nC")
+            .assert()
+            .stdout("@\n@\nC");
+    }
+
+    #[test]
+    fn test_sed_plus_invert() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-v", "--sed", r"/^\(B\|E\)$/,+1", "--", SED_CMD, "s/./@/"])
+            .write_stdin("A\nB\nC\nD\nE\nF\nG\n")
+            .assert()
+            .stdout("@\nB\nC\n@\nE\nF\n@\n");
+    }
+
+    #[test]
+    fn test_sed_invalid() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["--sed", "/B"])
+            .write_stdin("A\n")
+            .assert()
+            .code(1);
+    }
+
+    #[test]
+    fn test_where() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["--where", "$3 > 500 && $2 ~ /POST/", "--", SED_CMD, "s/./@/"])
+            .write_stdin("a GET 1000\nb POST 99\nc POST 501\n")
+            .assert()
+            .stdout("a GET 1000\nb POST 99\n@ POST 501\n");
+    }
+
+    #[test]
+    fn test_where_field() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-d", ",", "-f", "2", "--where", "$3 > 100", "--", SED_CMD, "s/./@/g"])
+            .write_stdin("a,bb,1000\nc,dd,99\ne,ff,101\n")
+            .assert()
+            .stdout("a,@@,1000\nc,dd,99\ne,@@,101\n");
+    }
+
+    #[test]
+    fn test_where_csv() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["--csv", "--where", "NR % 2 == 0 || $2 == \"x,y\"", "--", SED_CMD, "s/./@/"])
+            .write_stdin("a,\"x,y\"\nb,c\nd,e\n")
+            .assert()
+            .stdout("@,\"x,y\"\n@,c\nd,e\n");
+    }
+
+    #[test]
+    fn test_where_invalid() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["--where", "$1 >"])
+            .write_stdin("A\n")
+            .assert()
+            .code(1);
+    }
+
     #[test]
     fn test_character_range_error_c() {
         let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
@@ -277,6 +791,18 @@ mod cmdtest {
             .stdout("AAAAAA\nAAAAAA\n");
     }
 
+    #[test]
+    #[cfg(not(feature = "oniguruma"))]
+    fn test_onig_disabled() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-Gog", "\\d+", SED_CMD, "s/./@/g"])
+            .write_stdin("ABC123\n")
+            .assert()
+            .code(1)
+            .stdout("")
+            .stderr("teip: This build is not enabled 'oniguruma'. Build teip with --features oniguruma\n");
+    }
+
     #[test]
     #[cfg(feature = "oniguruma")]
     fn test_onig() {
@@ -635,6 +1161,389 @@ mod cmdtest {
                  .stdout("AAA,BBB,CCC\r\n1AAA,\"1BBB\r\nBB\",1CCC\r\n2AAA,2BBB,\"2CCC@\nCC\"\r\n");
     }
 
+    #[test]
+    fn test_field_multi_targets() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-f", "2", "--", SED_CMD, "s/./@/", ":::", "-f", "4-5", "--", TR_CMD, "a-z", "A-Z"])
+            .write_stdin("a b c d e\nf g h i j\n")
+            .assert()
+            .stdout("a @ c D E\nf @ h I J\n");
+    }
+
+    #[test]
+    fn test_field_multi_targets_overlap() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-d", ",", "-f", "1-2", "--", SED_CMD, "s/./@/", ":::", "-f2-3", TR_CMD, "a-z", "A-Z"])
+            .write_stdin("aa,bb,cc,dd\n")
+            .assert()
+            .stdout("@a,@b,CC,dd\n");
+    }
+
+    #[test]
+    fn test_solid_field_multi_targets() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-s", "-d", ",", "-f", "1", "--", SED_CMD, "s/./_/", ":::", "-f", "3", TR_CMD, "a-z", "A-Z"])
+            .write_stdin("aaa,bbb,ccc\nddd,eee,fff\n")
+            .assert()
+            .stdout("_aa,bbb,CCC\n_dd,eee,FFF\n");
+    }
+
+    #[test]
+    fn test_csv_multi_targets() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["--csv", "-f", "1", "--", SED_CMD, "s/./@/g", ":::", "-f", "3", "--", TR_CMD, "a-z", "A-Z"])
+            .write_stdin("ab,cd,ef\ngh,ij,kl\n")
+            .assert()
+            .stdout("@@,cd,EF\n@@,ij,KL\n");
+    }
+
+    #[test]
+    fn test_multi_targets_large() {
+        // The targets do not wait for each other even if their output exceeds the pipe buffer
+        let line = format!("a {{}} {}\n", "x".repeat(2000));
+        let input: String = (0..2000).map(|i| line.replace("{}", &i.to_string())).collect();
+        let expected: String = (0..2000).map(|i| line.replace("{}", &i.to_string()).replacen('a', "A", 1)).collect();
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-f", "1", "--", SED_CMD, "s/a/A/", ":::", "-f", "3", "--", "cat"])
+            .timeout(std::time::Duration::from_secs(10))
+            .write_stdin(input)
+            .assert()
+            .success()
+            .stdout(expected);
+    }
+
+    #[test]
+    fn test_multi_targets_without_list() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-f", "1", "--", SED_CMD, "s/./@/", ":::", "--", TR_CMD, "a-z", "A-Z"])
+            .write_stdin("abc\n")
+            .assert()
+            .code(1)
+            .stderr("teip: -f <list> is required after ':::'\n");
+    }
+
+    #[test]
+    fn test_builtin_field() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-f", "2", "--builtin", "upper"])
+            .write_stdin("hello world foo\ncard 1234567890123456 bar\n")
+            .assert()
+            .stdout("hello WORLD foo\ncard 1234567890123456 bar\n");
+    }
+
+    #[test]
+    fn test_builtin_redact() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-og", "[0-9]{8,}", "--builtin", "redact"])
+            .write_stdin("card 1234567890123456 bar\n")
+            .assert()
+            .stdout("card ************3456 bar\n");
+    }
+
+    #[test]
+    fn test_builtin_counter() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-l", "2-3", "--builtin", "counter:10:5"])
+            .write_stdin("a\nb\nc\n")
+            .assert()
+            .stdout("a\n10\n15\n");
+    }
+
+    #[test]
+    fn test_builtin_counter_overflow() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-l", "1,2", "--builtin", "counter:9223372036854775807"])
+            .write_stdin("a\nb\n")
+            .assert()
+            .code(1)
+            .stdout("9223372036854775807\n")
+            .stderr("teip: counter: The value exceeds the range of 64-bit integer (hole #2)\n");
+    }
+
+    #[test]
+    fn test_builtin_csv() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["--csv", "-f", "2", "--builtin", "upper"])
+            .write_stdin("a,\"b\nc\",d\n")
+            .assert()
+            .stdout("a,\"B\nC\",d\n");
+    }
+
+    #[test]
+    fn test_builtin_invalid_input() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-f", "2", "--builtin", "hex-decode"])
+            .write_stdin("a 4142 c\nd xyzw f\n")
+            .assert()
+            .code(1)
+            .stdout("a AB c\nd ")
+            .stderr("teip: hex-decode: Invalid character 'x' at position 0 (hole #2)\n");
+    }
+
+    #[test]
+    fn test_builtin_with_command() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-f", "2", "--builtin", "upper", "--", SED_CMD, "s/./@/"])
+            .write_stdin("a b c\n")
+            .assert()
+            .code(1)
+            .stderr("teip: --sink, --builtin, --script and --wasm cannot be used with <command> or -I\n");
+    }
+
+    #[test]
+    fn test_script() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-f", "2", "--script", "x.to_upper() + \"@\" + nr"])
+            .write_stdin("abc def ghi\njkl mno pqr\n")
+            .assert()
+            .stdout("abc DEF@1 ghi\njkl MNO@2 pqr\n");
+    }
+
+    #[test]
+    fn test_script_location() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-og", "[0-9]+", "--script", "if x.len() > 1 { `[${seq}:${match_no}:${offset}]` } else { x }"])
+            .write_stdin("a1 b22 c333\n")
+            .assert()
+            .stdout("a1 b[2:2:4] c[3:3:8]\n");
+    }
+
+    #[test]
+    fn test_script_error() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-f", "2", "--script", "x +"])
+            .write_stdin("abc def ghi\n")
+            .assert()
+            .code(1)
+            .stderr("teip: Failed to compile the script: Script is incomplete (line 1, position 4)\n");
+    }
+
+    #[test]
+    fn test_script_endless_loop() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-f", "2", "--script", "loop {}"])
+            .write_stdin("abc def ghi\n")
+            .assert()
+            .code(1)
+            .stdout("abc ")
+            .stderr("teip: Script error: Too many operations (line 1, position 6) (hole #1)\n");
+    }
+
+    #[test]
+    #[cfg(feature = "wasm")]
+    fn test_wasm() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-f", "2", "--wasm", "tests/wasm/upper.wat"])
+            .write_stdin("abc def ghi\njkl mno pqr\n")
+            .assert()
+            .stdout("abc DEF ghi\njkl MNO pqr\n");
+    }
+
+    #[test]
+    #[cfg(feature = "wasm")]
+    fn test_wasm_reset() {
+        // 640KiB of the holes in total. The module has only 64KiB of memory, which is freed after each hole.
+        let line = format!("abc {} ghi\n", "DEF".repeat(100));
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-f", "2", "--wasm", "tests/wasm/lower.wat"])
+            .write_stdin(line.repeat(1000))
+            .assert()
+            .stdout(line.replace("DEF", "def").repeat(1000));
+    }
+
+    #[test]
+    #[cfg(feature = "wasm")]
+    fn test_wasm_endless() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-f", "2", "--wasm", "tests/wasm/loop.wat"])
+            .timeout(std::time::Duration::from_secs(60))
+            .write_stdin("abc def ghi\n")
+            .assert()
+            .code(1)
+            .stdout("abc ")
+            .stderr("teip: WebAssembly error: Too many instructions (hole #1)\n");
+    }
+
+    #[test]
+    #[cfg(feature = "wasm")]
+    fn test_wasm_not_found() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-f", "2", "--wasm", "tests/wasm/nothing.wasm"])
+            .write_stdin("abc def ghi\n")
+            .assert()
+            .code(1);
+    }
+
+    #[test]
+    #[cfg(not(feature = "wasm"))]
+    fn test_wasm_disabled() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-f", "2", "--wasm", "tests/wasm/upper.wat"])
+            .write_stdin("abc def ghi\n")
+            .assert()
+            .code(1)
+            .stderr("teip: This build is not enabled 'wasm'. Build teip with --features wasm\n");
+    }
+
+    #[test]
+    fn test_sink_builtin() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-og", "[a-z]+", "--sink", "builtin:mask:*"])
+            .write_stdin("abc 123 def\n")
+            .assert()
+            .stdout("*** 123 ***\n");
+    }
+
+    #[test]
+    fn test_sink_invalid() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-f", "2", "--sink", "ftp:example"])
+            .write_stdin("a b c\n")
+            .assert()
+            .code(1)
+            .stderr("teip: --sink: Unknown sink 'ftp'. Use builtin:<name>, script:<file>, wasm:<file> or socket:<path>\n");
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-f", "2", "--sink", "builtin:upper", "--builtin", "lower"])
+            .write_stdin("a b c\n")
+            .assert()
+            .code(1)
+            .stderr("teip: Only one of --sink, --builtin, --script and --wasm can be specified\n");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_sink_socket() {
+        use std::io::{BufRead, BufReader, Write};
+        use std::os::unix::net::UnixListener;
+        let path = std::env::temp_dir().join(format!("teip-test-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            for line in BufReader::new(stream).lines() {
+                writer.write_all(format!("<{}>\n", line.unwrap()).as_bytes()).unwrap();
+            }
+        });
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-f", "2", "--sink", &format!("socket:{}", path.display())])
+            .write_stdin("a b c\nd e f\n")
+            .assert()
+            .stdout("a <b> c\nd <e> f\n");
+        server.join().unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_collect() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-d", ",", "-f", "2", "--collect", "--", "sort"])
+            .write_stdin("a,3,x\nb,1,y\nc,2,z\n")
+            .assert()
+            .stdout("a,1,x\nb,2,y\nc,3,z\n");
+    }
+
+    #[test]
+    fn test_collect_multi_targets() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-f", "1", "--collect", "--", "tac", ":::", "-f", "3", "--", "sort", "-r"])
+            .write_stdin("a 1 x\nb 2 z\nc 3 y\n")
+            .assert()
+            .stdout("c 1 z\nb 2 y\na 3 x\n");
+    }
+
+    #[test]
+    fn test_collect_mismatch() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-f", "2", "--collect", "--", "uniq"])
+            .write_stdin("a 1\nb 1\nc 2\n")
+            .assert()
+            .code(1)
+            .stdout("")
+            .stderr("teip: --collect: uniq printed 2 lines for 3 holes\n");
+    }
+
+    #[test]
+    fn test_collect_with_solid() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-f", "2", "-s", "--collect", "--", "sort"])
+            .write_stdin("a 1\n")
+            .assert()
+            .code(1)
+            .stderr("teip: --collect cannot be used with -s, -I, --sink, --builtin, --script or --wasm\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_pty() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-f", "2", "--pty", "--", "sh", "-c", "while read l; do [ -t 1 ] && echo tty || echo pipe; done"])
+            .write_stdin("a 1\nb 2\n")
+            .assert()
+            .stdout("a tty\nb tty\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_pty_multi_lines() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-f", "2", "--pty", "--", "sed", "s/./@/"])
+            .write_stdin("AAA BBB CCC\nDDD EEE FFF\nGGG HHH III\n")
+            .assert()
+            .stdout("AAA @BB CCC\nDDD @EE FFF\nGGG @HH III\n");
+    }
+
+    #[test]
+    fn test_line_buffered() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-f", "2", "--line-buffered", "--", "sed", "s/./@/"])
+            .write_stdin("AAA BBB CCC\nDDD EEE FFF\n")
+            .assert()
+            .stdout("AAA @BB CCC\nDDD @EE FFF\n");
+    }
+
+    #[test]
+    fn test_queue_size() {
+        // The command buffers the results until it receives all of the holes
+        let input: String = (1..=3000).map(|i| format!("{} AAA\n", i)).collect();
+        let expected: String = (1..=3000).map(|i| format!("{} @AA\n", i)).collect();
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-f", "2", "--queue-size", "1K", "--", "sed", "s/./@/"])
+            .write_stdin(input.as_str())
+            .assert()
+            .stdout(expected);
+    }
+
+    #[test]
+    fn test_queue_size_solid() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-s", "-f", "2", "--queue-size", "0", "--", "sed", "s/./@/"])
+            .write_stdin("1 AAA\n2 BBB\n3 CCC\n")
+            .assert()
+            .stdout("1 @AA\n2 @BB\n3 @CC\n");
+    }
+
+    #[test]
+    fn test_queue_size_invalid() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        let output = cmd.args(&["-f", "2", "--queue-size", "1X", "--", "cat"])
+            .write_stdin("1 AAA\n")
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(1));
+        assert!(String::from_utf8_lossy(&output.stderr).contains("Invalid size '1X'"));
+    }
+
+    #[test]
+    fn test_pty_with_solid() {
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+        cmd.args(&["-f", "2", "-s", "--pty", "--", "cat"])
+            .write_stdin("a 1\n")
+            .assert()
+            .code(1)
+            .stderr("teip: --pty cannot be used with -s, -I, --collect, --sink, --builtin, --script or --wasm\n");
+    }
+
     #[test]
     fn test_solid_field() {
         let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
@@ -828,6 +1737,135 @@ mod cmdtest {
 
     cfg_if::cfg_if! {
         if #[cfg(not(windows))] {
+            #[test]
+            fn test_child_status_failed() {
+                let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+                cmd.args(&["-l", "2", "--", "sh", "-c", "sed 's/./@/g'; exit 3"])
+                    .write_stdin("AAA\nBBB\nCCC\n")
+                    .assert()
+                    .stdout("AAA\n@@@\nCCC\n")
+                    .stderr("teip: sh exited with status 3\n")
+                    .code(123);
+            }
+
+            #[test]
+            fn test_child_status_crashed() {
+                let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+                cmd.args(&["-l", "2-3", "--", "sh", "-c", "read x; echo $x; exit 3"])
+                    .write_stdin("AAA\nBBB\nCCC\n")
+                    .assert()
+                    .stdout("AAA\nBBB\n")
+                    .stderr("teip: Output of targeted command has been exhausted\nteip: sh exited with status 3\n")
+                    .code(123);
+            }
+
+            #[test]
+            fn test_child_status_signaled() {
+                let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+                cmd.args(&["-l", "2", "--", "sh", "-c", "cat; kill -9 $$"])
+                    .write_stdin("AAA\nBBB\nCCC\n")
+                    .assert()
+                    .stdout("AAA\nBBB\nCCC\n")
+                    .stderr("teip: sh was terminated by signal 9\n")
+                    .code(125);
+            }
+
+            #[test]
+            fn test_child_status_solid() {
+                let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+                cmd.args(&["-s", "-l", "1,3", "--", "sh", "-c", "grep A"])
+                    .write_stdin("AAA\nBBB\nCCC\n")
+                    .assert()
+                    .stdout("AAA\nBBB\n\n")
+                    .stderr("teip: sh (hole #2) exited with status 1\n")
+                    .code(123);
+            }
+
+            #[test]
+            fn test_ignore_child_status() {
+                let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+                cmd.args(&["--ignore-child-status", "-s", "-l", "1,3", "--", "sh", "-c", "grep A"])
+                    .write_stdin("AAA\nBBB\nCCC\n")
+                    .assert()
+                    .stdout("AAA\nBBB\n\n")
+                    .stderr("")
+                    .code(0);
+            }
+
+            #[test]
+            fn test_solid_location_envs() {
+                let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+                cmd.args(&["-s", "-f", "2", "--", "sh", "-c", "cat >/dev/null; echo $TEIP_LINE_NUMBER:$TEIP_FIELD_INDEX:$TEIP_HOLE_SEQ:$TEIP_BYTE_OFFSET"])
+                    .write_stdin("a b c\nd e f\n")
+                    .assert()
+                    .stdout("a 1:2:1:2 c\nd 2:2:2:8 f\n");
+            }
+
+            #[test]
+            fn test_solid_location_envs_match() {
+                let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+                cmd.args(&["-s", "-og", "[0-9]+", "--", "sh", "-c", "cat >/dev/null; echo ${TEIP_MATCH_INDEX}${TEIP_FIELD_INDEX}"])
+                    .write_stdin("a10b20c30\nd40\n")
+                    .assert()
+                    .stdout("a1b2c3\nd1\n");
+            }
+
+            #[test]
+            fn test_solid_replace_location() {
+                let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+                cmd.args(&["-I", "{}", "-f", "2", "--", _ECHO_CMD, "{nr}-{field}:{}"])
+                    .write_stdin("a b c\nd {nr} f\n")
+                    .assert()
+                    .stdout("a 1-2:b c\nd 2-2:{nr} f\n");
+            }
+
+            #[test]
+            fn test_shell() {
+                let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+                cmd.args(&["-S", "-f", "2", "--", "sed 's/./@/' | tr a-z A-Z"])
+                    .write_stdin("abc def ghi\njkl mno pqr\n")
+                    .assert()
+                    .stdout("abc @EF ghi\njkl @NO pqr\n");
+            }
+
+            #[test]
+            fn test_shell_solid_replace() {
+                let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+                cmd.args(&["--shell", "-I", "{}", "-f", "2", "--", "echo {} | tr a-z A-Z"])
+                    .write_stdin("abc def ghi\njkl mno pqr\n")
+                    .assert()
+                    .stdout("abc DEF ghi\njkl MNO pqr\n");
+            }
+
+            #[test]
+            fn test_shell_args() {
+                // The arguments after the first one keep their quoting
+                let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+                cmd.args(&["-S", "-f", "2", "--", "sed 's/./@/' | sed", "s/$/ !/"])
+                    .write_stdin("abc def ghi\n")
+                    .assert()
+                    .stdout("abc @ef ! ghi\n");
+            }
+
+            #[test]
+            fn test_shell_solid_replace_quoted() {
+                // The hole is not interpreted by the shell
+                let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+                cmd.args(&["-S", "-I", "{}", "-d", ",", "-f", "2", "--", "echo {}"])
+                    .write_stdin("a,$(echo X);echo Y 'b,c\n")
+                    .assert()
+                    .stdout("a,$(echo X);echo Y 'b,c\n");
+            }
+
+            #[test]
+            fn test_shell_env() {
+                let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+                cmd.env("TEIP_SHELL", "bash")
+                    .args(&["-s", "-S", "-l", "2", "--", "cat >/dev/null; echo $0"])
+                    .write_stdin("AAA\nBBB\n")
+                    .assert()
+                    .stdout("AAA\nbash\n");
+            }
         }
     }
 
@@ -0,0 +1,12 @@
+;; Plugin for the tests of --wasm. teip_transform never returns.
+(module
+  (memory (export "memory") 1 1)
+
+  (func (export "teip_alloc") (param $len i32) (result i32)
+    (i32.const 0))
+
+  (func (export "teip_transform") (param $ptr i32) (param $len i32) (result i64)
+    (loop $forever
+      (br $forever))
+    (i64.const 0))
+)
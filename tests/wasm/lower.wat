@@ -0,0 +1,36 @@
+;; Plugin for the tests of --wasm. Convert A-Z to a-z.
+;; Allocate the buffers one after another, and free all of them by teip_reset after each hole.
+(module
+  (memory (export "memory") 1 1)
+  (global $next (mut i32) (i32.const 0))
+
+  (func $alloc (export "teip_alloc") (param $len i32) (result i32)
+    (local $ptr i32)
+    (local.set $ptr (global.get $next))
+    ;; Out of memory. Only one page is available.
+    (if (i32.gt_u (i32.add (local.get $ptr) (local.get $len)) (i32.const 65536))
+      (then (unreachable)))
+    (global.set $next (i32.add (local.get $ptr) (local.get $len)))
+    (local.get $ptr))
+
+  (func (export "teip_reset")
+    (global.set $next (i32.const 0)))
+
+  (func (export "teip_transform") (param $ptr i32) (param $len i32) (result i64)
+    (local $out i32)
+    (local $i i32)
+    (local $c i32)
+    (local.set $out (call $alloc (local.get $len)))
+    (block $done
+      (loop $next
+        (br_if $done (i32.ge_u (local.get $i) (local.get $len)))
+        (local.set $c (i32.load8_u (i32.add (local.get $ptr) (local.get $i))))
+        (if (i32.and (i32.ge_u (local.get $c) (i32.const 65)) (i32.le_u (local.get $c) (i32.const 90)))
+          (then (local.set $c (i32.add (local.get $c) (i32.const 32)))))
+        (i32.store8 (i32.add (local.get $out) (local.get $i)) (local.get $c))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next)))
+    (i64.or
+      (i64.shl (i64.extend_i32_u (local.get $out)) (i64.const 32))
+      (i64.extend_i32_u (local.get $len))))
+)